@@ -1,14 +1,19 @@
 use crate::token;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Ast<'a> {
     Literal(&'a str),
-    Interpolation(token::Name<'a>),
-    UnescapedInterpolation(token::Name<'a>),
+    Interpolation(token::Name<'a>, Option<&'a str>),
+    UnescapedInterpolation(token::Name<'a>, Option<&'a str>),
     Sequence(Vec<Ast<'a>>),
     Iteration {
         name: token::Name<'a>,
         nested: Box<Ast<'a>>,
+        // Rendered between the items, from {{:sep}} to the section closer
+        separator: Option<Box<Ast<'a>>>,
+        // Rendered when there are no items, from {{else}} to the section
+        // closer
+        otherwise: Option<Box<Ast<'a>>>,
     },
     NegativeIteration {
         name: token::Name<'a>,
@@ -17,17 +22,52 @@ pub enum Ast<'a> {
     Conditional {
         name: token::Name<'a>,
         nested: Box<Ast<'a>>,
+        otherwise: Option<Box<Ast<'a>>>,
     },
     NegativeConditional {
         name: token::Name<'a>,
         nested: Box<Ast<'a>>,
+        otherwise: Option<Box<Ast<'a>>>,
     },
     Scope {
         name: token::Name<'a>,
         nested: Box<Ast<'a>>,
+        otherwise: Option<Box<Ast<'a>>>,
+    },
+    // {{#if a}}...{{elif b}}...{{else}}...{{/if}}, with a branch for each
+    // condition
+    If {
+        branches: Vec<(token::Expression<'a>, Ast<'a>)>,
+        otherwise: Option<Box<Ast<'a>>>,
+    },
+    // {{#match name}}{{:A}}...{{:B}}...{{/match}}, with the path of the
+    // variant for each arm
+    Match {
+        name: token::Name<'a>,
+        arms: Vec<(&'a str, Ast<'a>)>,
+        otherwise: Option<Box<Ast<'a>>>,
     },
     PartialInclude {
         partial_name: &'a str,
-        root: token::Name<'a>,
+        root: token::PartialRoot<'a>,
+    },
+    // {{$name}}...{{/name}}, a block of a parent template, rendering the
+    // default content unless a child template overrides it
+    Block {
+        name: &'a str,
+        nested: Box<Ast<'a>>,
+    },
+    // {{<parent}}{{$name}}...{{/name}}{{/parent}}, rendering the parent
+    // template with the given blocks overridden. It is replaced by the
+    // parent template before code is generated.
+    Parent {
+        partial_name: &'a str,
+        blocks: Vec<(&'a str, Ast<'a>)>,
+    },
+    // {{*define name}}...{{/define}}, defining a partial that can be
+    // included in the rest of the template. It renders nothing by itself.
+    Define {
+        partial_name: &'a str,
+        nested: Box<Ast<'a>>,
     },
 }
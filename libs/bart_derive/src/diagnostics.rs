@@ -0,0 +1,280 @@
+use crate::parser;
+use crate::scanner;
+use crate::token::Token;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    // Byte offset into the template where the problem is
+    pub offset: usize,
+    pub message: String,
+    // Further locations involved in the problem, such as where an unclosed
+    // section was opened
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Note {
+    pub offset: usize,
+    pub message: String,
+}
+
+// The byte offset of `part` in `template`. `part` must be a slice of
+// `template`, which is true for all text referenced by tokens and the AST.
+pub fn offset_of(template: &str, part: &str) -> usize {
+    let offset = (part.as_ptr() as usize).wrapping_sub(template.as_ptr() as usize);
+    assert!(
+        offset <= template.len(),
+        "Text must be a slice of the template"
+    );
+    offset
+}
+
+fn token_text<'a>(token: &Token<'a>) -> &'a str {
+    match *token {
+        Token::Literal(text)
+        | Token::Comment(text)
+        | Token::Separator(text)
+        | Token::Else(text)
+        | Token::Arm(text) => text,
+        Token::Interpolation(ref name, _)
+        | Token::UnescapedInterpolation(ref name, _)
+        | Token::SectionOpener(_, ref name)
+        | Token::SectionCloser(ref name)
+        | Token::If(ref name, _)
+        | Token::Match(ref name, _)
+        | Token::Parent(ref name)
+        | Token::Block(ref name)
+        | Token::Define(ref name, _) => name.text,
+        Token::ElseIf(keyword, _) => keyword,
+        Token::PartialInclude(partial_name, _) => partial_name,
+        Token::SetDelimiters(delimiters) => delimiters.opener,
+        Token::TrimWhitespace => "",
+    }
+}
+
+fn line_number(template: &str, offset: usize) -> usize {
+    template[..offset].matches('\n').count() + 1
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "end of template".to_owned(),
+        Some(Token::Literal(_)) => "text".to_owned(),
+        Some(Token::Interpolation(name, _)) | Some(Token::UnescapedInterpolation(name, _)) => {
+            format!("interpolation of `{}`", name.text)
+        }
+        Some(Token::SectionOpener(_, name)) => format!("section opener for `{}`", name.text),
+        Some(Token::SectionCloser(name)) => format!("section closer for `{}`", name.text),
+        Some(Token::PartialInclude(partial_name, _)) => {
+            format!("partial include of `{}`", partial_name)
+        }
+        Some(Token::Parent(name)) => format!("parent template `{}`", name.text),
+        Some(Token::Block(name)) => format!("block `{}`", name.text),
+        Some(Token::Define(_, partial_name)) => {
+            format!("definition of partial `{}`", partial_name)
+        }
+        Some(Token::Separator(_)) => "separator".to_owned(),
+        Some(Token::Arm(variant)) => format!("match arm for `{}`", variant),
+        Some(token) => format!("`{}`", token_text(token)),
+    }
+}
+
+impl Diagnostic {
+    pub fn from_scanner_error(error: scanner::Error) -> Diagnostic {
+        Diagnostic {
+            offset: error.offset,
+            message: match error.kind {
+                scanner::ErrorKind::Mismatch => "Invalid tag".to_owned(),
+                scanner::ErrorKind::UnclosedTag => "Unclosed tag".to_owned(),
+            },
+            notes: vec![],
+        }
+    }
+
+    pub fn from_parser_error(template: &str, error: parser::Error) -> Diagnostic {
+        match error {
+            parser::Error::Mismatch { expected, found } => Diagnostic {
+                offset: match found {
+                    Some(ref token) => offset_of(template, token_text(token)),
+                    None => template.len(),
+                },
+                message: format!(
+                    "Expected {}, found {}",
+                    expected,
+                    describe(found.as_deref())
+                ),
+                notes: vec![],
+            },
+            parser::Error::UnclosedSection { opener } => {
+                let opener_offset = offset_of(template, opener.text);
+                Diagnostic {
+                    offset: opener_offset,
+                    message: format!(
+                        "Section `{}` opened at line {} is never closed",
+                        opener.text,
+                        line_number(template, opener_offset)
+                    ),
+                    notes: vec![Note {
+                        offset: template.len(),
+                        message: format!(
+                            "Expected section closer for `{}` before the end of the template",
+                            opener.text
+                        ),
+                    }],
+                }
+            }
+            parser::Error::MismatchedSectionCloser { opener, closer } => {
+                let opener_offset = offset_of(template, opener.text);
+                Diagnostic {
+                    offset: offset_of(template, closer.text),
+                    message: format!(
+                        "Section closer for `{}` does not match section `{}` opened at line {}",
+                        closer.text,
+                        opener.text,
+                        line_number(template, opener_offset)
+                    ),
+                    notes: vec![Note {
+                        offset: opener_offset,
+                        message: format!("Section `{}` opened here", opener.text),
+                    }],
+                }
+            }
+            parser::Error::StraySectionCloser { closer } => Diagnostic {
+                offset: offset_of(template, closer.text),
+                message: format!(
+                    "Section closer for `{}` has no matching section opener",
+                    closer.text
+                ),
+                notes: vec![],
+            },
+        }
+    }
+
+    /// Format the diagnostic with the file name, line and column of the
+    /// problem, followed by the offending line with a caret under the
+    /// location, like rustc does. Notes are formatted the same way.
+    pub fn render(&self, path: &str, template: &str) -> String {
+        let mut rendered = snippet(path, template, self.offset, &self.message);
+        for note in &self.notes {
+            rendered.push('\n');
+            rendered.push_str(&snippet(
+                path,
+                template,
+                note.offset,
+                &format!("note: {}", note.message),
+            ));
+        }
+        rendered
+    }
+}
+
+fn snippet(path: &str, template: &str, offset: usize, message: &str) -> String {
+    let line_start = template[..offset].rfind('\n').map_or(0, |x| x + 1);
+    let line_end = template[offset..]
+        .find('\n')
+        .map_or(template.len(), |x| offset + x);
+    let line = template[line_start..line_end].trim_end_matches('\r');
+
+    let line_number = line_number(template, line_start);
+    let before = &template[line_start..offset];
+    let column = before.chars().count() + 1;
+
+    // Keep tabs in the indentation of the caret so it lines up
+    let indentation: String = before
+        .chars()
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let gutter = " ".repeat(line_number.to_string().len());
+
+    format!(
+        "{}:{}:{}: {}\n{} |\n{} | {}\n{} | {}^",
+        path, line_number, column, message, gutter, line_number, line, gutter, indentation
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{sequence, DEFAULT_DELIMITERS};
+
+    #[test]
+    fn it_finds_offsets_of_slices() {
+        let template = "Hello {{name}}";
+        assert_eq!(6, offset_of(template, &template[6..]));
+    }
+
+    #[test]
+    fn it_renders_location() {
+        let diagnostic = Diagnostic {
+            offset: 11,
+            message: "Unclosed tag".to_owned(),
+            notes: vec![],
+        };
+        assert_eq!(
+            "file.html:2:7: Unclosed tag\n  |\n2 | Hello {{name\n  |       ^",
+            diagnostic.render("file.html", "<p>\r\nHello {{name\n</p>")
+        );
+    }
+
+    #[test]
+    fn it_renders_location_in_first_line() {
+        let diagnostic = Diagnostic {
+            offset: 1,
+            message: "Invalid tag".to_owned(),
+            notes: vec![],
+        };
+        assert_eq!(
+            "file.html:1:2: Invalid tag\n  |\n1 | \t{{+}}\n  | \t^",
+            diagnostic.render("file.html", "\t{{+}}")
+        );
+    }
+
+    #[test]
+    fn it_locates_parser_errors() {
+        let template = "{{#a}}\n  {{/b}}";
+        let error = parser::parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap_err();
+        let diagnostic = Diagnostic::from_parser_error(template, error);
+
+        assert_eq!(12, diagnostic.offset);
+        assert_eq!(
+            "Section closer for `b` does not match section `a` opened at line 1",
+            diagnostic.message
+        );
+        assert_eq!(
+            vec![Note {
+                offset: 3,
+                message: "Section `a` opened here".to_owned()
+            }],
+            diagnostic.notes
+        );
+    }
+
+    #[test]
+    fn it_renders_notes() {
+        let template = "<ul>\n{{#items}}\n<li>{{.}}</li>\n</ul>";
+        let error = parser::parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap_err();
+        let diagnostic = Diagnostic::from_parser_error(template, error);
+
+        assert_eq!(
+            "file.html:2:4: Section `items` opened at line 2 is never closed\n  \
+             |\n2 | {{#items}}\n  |    ^\n\
+             file.html:4:6: note: Expected section closer for `items` before the end of the template\n  \
+             |\n4 | </ul>\n  |      ^",
+            diagnostic.render("file.html", template)
+        );
+    }
+
+    #[test]
+    fn it_locates_stray_section_closers() {
+        let template = "text {{/a}}";
+        let error = parser::parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap_err();
+        let diagnostic = Diagnostic::from_parser_error(template, error);
+
+        assert_eq!(8, diagnostic.offset);
+        assert_eq!(
+            "Section closer for `a` has no matching section opener",
+            diagnostic.message
+        );
+    }
+}
@@ -1,116 +1,828 @@
 use crate::ast;
+use crate::span::Source;
 use crate::token;
-use itertools;
+use proc_macro2::{Ident, Span, TokenStream};
 use quote::*;
-use syn;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 
 pub trait PartialsResolver {
-    fn generate_partial(&mut self, partial_name: &str) -> quote::Tokens;
+    /// The code for rendering the partial, where `in_section` tells whether
+    /// the include is in a section that is rendered depending on the data,
+    /// and `arguments` is the struct of the scope for an include with named
+    /// arguments
+    fn generate_partial(
+        &mut self,
+        partial_name: &str,
+        in_section: bool,
+        arguments: Option<ArgumentsStruct>,
+    ) -> TokenStream;
 }
 
-fn resolve(name: &token::Name, scope_depth: u32) -> syn::Ident {
-    use itertools::Itertools;
+/// The `_ArgumentsN` struct for the scope of a partial included with named
+/// arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgumentsStruct {
+    pub index: usize,
+    pub parameters: usize,
+}
+
+// An enclosing iteration section, for resolving loop variables like @index.
+// Some loop variables require more work in the loop, so that is only done
+// when they are used.
+struct Loop {
+    scope_level: u32,
+    uses_last: Cell<bool>,
+    uses_length: Cell<bool>,
+}
+
+// An enclosing match arm. Only the fields of the variant that are used in
+// the arm are bound in its pattern, so they are recorded as they are used.
+struct Arm {
+    scope_level: u32,
+    fields: RefCell<Vec<String>>,
+}
+
+/// Everything besides the AST that is needed to generate code for a template
+pub struct Context<'a> {
+    source: &'a Source<'a>,
+
+    // The module of user-defined filters, from #[template_filters]
+    filters: Option<&'a syn::Path>,
+
+    partials_resolver: &'a mut dyn PartialsResolver,
+
+    // The sorted parameters of the partials included with named arguments,
+    // one for each `_ArgumentsN` struct, shared by all the templates and
+    // partials of a derive
+    arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+
+    // The types of the fields of the template root, when it is known, for
+    // naming the variants in match sections
+    field_types: Option<&'a HashMap<String, syn::Path>>,
+
+    loops: Vec<Loop>,
+    arms: Vec<Arm>,
 
-    let root = match name.leading_dots {
-        0 => "_s0".to_owned(),
-        x => {
-            let level = scope_depth.checked_sub(x).unwrap_or_else(|| {
-                panic!(
+    // The number of enclosing sections that are rendered depending on the
+    // data, which is what ends the recursion of recursive partials
+    sections: u32,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        source: &'a Source<'a>,
+        filters: Option<&'a syn::Path>,
+        partials_resolver: &'a mut dyn PartialsResolver,
+        arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+        field_types: Option<&'a HashMap<String, syn::Path>>,
+    ) -> Context<'a> {
+        Context {
+            source,
+            filters,
+            partials_resolver,
+            arguments_structs,
+            field_types,
+            loops: vec![],
+            arms: vec![],
+            sections: 0,
+        }
+    }
+}
+
+fn member(segment: &str, span: Span) -> TokenStream {
+    if let Ok(index) = segment.parse::<u32>() {
+        return syn::Index { index, span }.into_token_stream();
+    }
+
+    // Both `type` and `r#type` refer to the field `r#type`
+    let ident = segment.strip_prefix("r#").unwrap_or(segment);
+    match syn::parse_str::<Ident>(ident) {
+        Ok(_) => Ident::new(ident, span),
+        Err(_) => Ident::new_raw(ident, span),
+    }
+    .into_token_stream()
+}
+
+/// The expression for accessing the fields `segments` of `root`, as in
+/// `root.a.b`
+pub fn field_path(root: TokenStream, segments: &[&str], span: Span) -> TokenStream {
+    let members = segments.iter().map(|segment| member(segment, span));
+    quote! { #root #(.#members)* }
+}
+
+// The span of the generated code for a tag. It must resolve at the call site,
+// like the variable declarations, but should be located at the tag for error
+// messages
+fn tag_span(name: &token::Name, source: &Source) -> Span {
+    Span::call_site().located_at(source.span_of(name.text))
+}
+
+fn name_error(name: &token::Name, message: String, source: &Source) -> TokenStream {
+    source.error_at(name.text, message)
+}
+
+// The path followed by the segments of the name, with their calls, as in
+// `_s0.user().price_in(&_s0.currency)`
+fn segments_path(
+    root: TokenStream,
+    name: &token::Name,
+    segments: &[token::Segment],
+    scope_depth: u32,
+    context: &Context,
+) -> TokenStream {
+    let span = tag_span(name, context.source);
+    segments.iter().fold(root, |path, segment| {
+        // A call without a name calls the value itself, as in {{.()}}
+        let path = match segment.name {
+            "" => path,
+            field => {
+                let member = member(field, span);
+                quote! { #path.#member }
+            }
+        };
+        match segment.call {
+            Some(ref arguments) => {
+                let arguments = arguments.iter().map(|x| argument(x, scope_depth, context));
+                quote_spanned! { span=> #path(#(#arguments),*) }
+            }
+            None => path,
+        }
+    })
+}
+
+fn resolve(name: &token::Name, scope_depth: u32, context: &Context) -> TokenStream {
+    let source = context.source;
+    let span = tag_span(name, source);
+
+    let level = match name.leading_dots {
+        0 => 0,
+        x => match scope_depth.checked_sub(x) {
+            Some(level) => level,
+            None => {
+                let message = format!(
                     "Too many leading dots ({}) in scope depth of only {}",
                     x, scope_depth
                 );
-            });
-            format!("_s{}", level)
+                return name_error(name, message, source);
+            }
+        },
+    };
+
+    let root = Ident::new(&format!("_s{}", level), span);
+    segments_path(
+        root.into_token_stream(),
+        name,
+        &name.segments,
+        scope_depth,
+        context,
+    )
+}
+
+// A loop variable, such as @index. Without leading dots, it refers to the
+// innermost iteration section, otherwise to the iteration section at that
+// scope, like other names.
+fn loop_variable(name: &token::Name, scope_depth: u32, context: &Context) -> TokenStream {
+    let source = context.source;
+    let span = tag_span(name, source);
+    let variable = name.segments[0].name;
+
+    let level = match name.leading_dots {
+        0 => context.loops.last().map(|x| x.scope_level),
+        x => scope_depth.checked_sub(x),
+    };
+    let enclosing = match context.loops.iter().find(|x| Some(x.scope_level) == level) {
+        Some(enclosing) => enclosing,
+        None => {
+            let message = format!("`{}` is only available in iteration sections", variable);
+            return name_error(name, message, source);
+        }
+    };
+
+    let level = enclosing.scope_level;
+    let index = Ident::new(&format!("_i{}", level), span);
+    match variable {
+        "@index" => index.into_token_stream(),
+        "@index1" => quote_spanned! { span=> (#index + 1) },
+        "@first" => quote_spanned! { span=> (#index == 0) },
+        "@last" => {
+            enclosing.uses_last.set(true);
+            Ident::new(&format!("_last{}", level), span).into_token_stream()
+        }
+        "@length" => {
+            enclosing.uses_length.set(true);
+            Ident::new(&format!("_len{}", level), span).into_token_stream()
+        }
+        _ => {
+            let message = format!(
+                "Unknown loop variable `{}`. Expected one of @index, @index1, @first, @last or @length",
+                variable
+            );
+            name_error(name, message, source)
+        }
+    }
+}
+
+// A field of the variant of an enclosing match arm, such as `.until` in
+// {{:Suspended}}, which refers to the binding of the field in the pattern.
+// Other names, like `.` for the entire value, are resolved as usual.
+fn variant_field(name: &token::Name, scope_depth: u32, context: &Context) -> Option<TokenStream> {
+    // Calls, as in {{.len()}}, are methods of the entire value
+    match name.segments.first() {
+        Some(segment) if name.leading_dots > 0 && segment.call.is_none() => (),
+        _ => return None,
+    }
+
+    let level = scope_depth.checked_sub(name.leading_dots)?;
+    let arm = context.arms.iter().find(|x| x.scope_level == level)?;
+
+    let field = name.segments[0].name;
+    let field = field.strip_prefix("r#").unwrap_or(field);
+    let mut fields = arm.fields.borrow_mut();
+    let index = match fields.iter().position(|x| x == field) {
+        Some(index) => index,
+        None => {
+            fields.push(field.to_owned());
+            fields.len() - 1
         }
     };
 
-    let mut full_name = itertools::chain(&[root.as_str()], &name.segments).join(".");
+    let span = tag_span(name, context.source);
+    let binding = Ident::new(&format!("_f{}_{}", level, index), span);
+    Some(segments_path(
+        binding.into_token_stream(),
+        name,
+        &name.segments[1..],
+        scope_depth,
+        context,
+    ))
+}
+
+// The expression for the value of a name, which may be a loop variable or a
+// field of a variant
+fn expression(name: &token::Name, scope_depth: u32, context: &Context) -> TokenStream {
+    match name.segments.first() {
+        Some(segment) if segment.name.starts_with('@') => loop_variable(name, scope_depth, context),
+        _ => variant_field(name, scope_depth, context)
+            .unwrap_or_else(|| resolve(name, scope_depth, context)),
+    }
+}
+
+// The path of the variant of a match arm. Unqualified variants, as in
+// {{:Active}}, are looked up in the type of the field that is matched, when
+// it is known.
+fn variant_path(variant: &str, name: &token::Name, context: &Context) -> Result<syn::Path, String> {
+    let span = Span::call_site().located_at(context.source.span_of(variant));
+    let mut path = syn::parse_str::<syn::Path>(variant).unwrap();
+    for segment in &mut path.segments {
+        segment.ident.set_span(span);
+    }
+    if path.segments.len() > 1 {
+        return Ok(path);
+    }
+
+    let field_type = match (name.leading_dots, &name.segments[..]) {
+        (0, [field]) if field.call.is_none() => {
+            let field = field.name.strip_prefix("r#").unwrap_or(field.name);
+            context.field_types.and_then(|types| types.get(field))
+        }
+        _ => None,
+    };
+    match field_type {
+        Some(field_type) => {
+            let mut qualified = field_type.clone();
+            qualified.segments.extend(path.segments);
+            Ok(qualified)
+        }
+        None => Err(format!(
+            "Unable to determine the type of `{}`. Qualify the variant, as in `{{{{:Type::{}}}}}`",
+            name.text, variant
+        )),
+    }
+}
+
+const BUILTIN_FILTERS: &[&str] = &[
+    "upper",
+    "lower",
+    "trim",
+    "truncate",
+    "default",
+    "join",
+    "capitalize",
+    "urlencode",
+    "pluralize",
+];
+
+// The tokens of `text`, which is Rust code in the template, such as a
+// literal, located at the text
+fn template_tokens(text: &str, source: &Source) -> TokenStream {
+    let span = Span::call_site().located_at(source.span_of(text));
+    text.parse::<TokenStream>()
+        .unwrap()
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(span);
+            token
+        })
+        .collect()
+}
+
+// An argument to a filter or function call. Names are passed by reference.
+fn argument(argument: &token::Argument, scope_depth: u32, context: &Context) -> TokenStream {
+    match *argument {
+        token::Argument::Literal(text) => template_tokens(text, context.source),
+        token::Argument::Name(ref name) => {
+            let span = tag_span(name, context.source);
+            let name = expression(name, scope_depth, context);
+            quote_spanned! { span=> &#name }
+        }
+    }
+}
+
+fn operand(argument: &token::Argument, scope_depth: u32, context: &Context) -> TokenStream {
+    match *argument {
+        token::Argument::Literal(text) => template_tokens(text, context.source),
+        token::Argument::Name(ref name) => expression(name, scope_depth, context),
+    }
+}
+
+// The scope of a partial included with named arguments, as in
+// {{>button label="Save"}}. All includes with the same parameters share an
+// `_ArgumentsN` struct, declared by `arguments_structs`, so the scope of a
+// partial has the same type wherever it is included.
+fn partial_arguments(
+    arguments: &[(&str, token::Argument)],
+    scope_depth: u32,
+    context: &Context,
+) -> (TokenStream, ArgumentsStruct) {
+    let mut parameters = arguments
+        .iter()
+        .map(|&(parameter, _)| parameter.strip_prefix("r#").unwrap_or(parameter).to_owned())
+        .collect::<Vec<_>>();
+    parameters.sort();
+
+    let mut structs = context.arguments_structs.borrow_mut();
+    let index = match structs.iter().position(|x| *x == parameters) {
+        Some(index) => index,
+        None => {
+            structs.push(parameters);
+            structs.len() - 1
+        }
+    };
+    drop(structs);
+    let name = format_ident!("_Arguments{}", index);
+
+    let members = arguments.iter().map(|&(parameter, _)| {
+        let span = Span::call_site().located_at(context.source.span_of(parameter));
+        member(parameter, span)
+    });
+    let values = arguments.iter().map(|(_, value)| {
+        let value = operand(value, scope_depth, context);
+        quote! { &#value }
+    });
+
+    let root = quote! {
+        let _s0 = &#name {
+            #(#members: #values,)*
+        };
+    };
+    let arguments = ArgumentsStruct {
+        index,
+        parameters: arguments.len(),
+    };
+    (root, arguments)
+}
+
+/// The structs for the scopes of partials included with named arguments,
+/// with a field for each of the sorted `arguments`. The fields refer to the
+/// values of the arguments, and are generic over their types.
+pub fn arguments_structs(arguments: &[Vec<String>]) -> TokenStream {
+    let structs = arguments.iter().enumerate().map(|(index, parameters)| {
+        let name = format_ident!("_Arguments{}", index);
+        let members = parameters
+            .iter()
+            .map(|parameter| member(parameter, Span::call_site()));
+        let types = (0..parameters.len())
+            .map(|index| format_ident!("T{}", index))
+            .collect::<Vec<_>>();
+        quote! {
+            #[allow(dead_code)]
+            struct #name<'a, #(#types: ?Sized),*> {
+                #(#members: &'a #types,)*
+            }
+        }
+    });
+    quote! { #(#structs)* }
+}
+
+// The Rust expression for the condition of an if section. Parentheses are
+// only added where they are needed, to avoid warnings about unused ones.
+fn condition(condition: &token::Expression, scope_depth: u32, context: &Context) -> TokenStream {
+    use token::Expression::*;
+
+    let grouped = |inner: &token::Expression, group: bool| {
+        let inner = self::condition(inner, scope_depth, context);
+        match group {
+            true => quote! { (#inner) },
+            false => inner,
+        }
+    };
+
+    match *condition {
+        Operand(token::Argument::Name(ref name)) => {
+            let span = tag_span(name, context.source);
+            let value = expression(name, scope_depth, context);
+            quote_spanned! { span=> _bart::Conditional::val(&#value) }
+        }
+        Operand(ref literal) => operand(literal, scope_depth, context),
+        Comparison(ref lhs, operator, ref rhs) => {
+            let lhs = compared(lhs, scope_depth, context);
+            let operator = template_tokens(operator, context.source);
+            let rhs = compared(rhs, scope_depth, context);
+            quote! { #lhs #operator #rhs }
+        }
+        Not(ref inner) => {
+            let inner = grouped(inner, !matches!(**inner, Operand(_) | Not(_)));
+            quote! { !#inner }
+        }
+        And(ref lhs, ref rhs) => {
+            let lhs = grouped(lhs, matches!(**lhs, Or(..)));
+            let rhs = grouped(rhs, matches!(**rhs, Or(..)));
+            quote! { #lhs && #rhs }
+        }
+        Or(ref lhs, ref rhs) => {
+            let lhs = grouped(lhs, false);
+            let rhs = grouped(rhs, false);
+            quote! { #lhs || #rhs }
+        }
+    }
+}
+
+// An operand of a comparison. Names are compared as they are, rather than
+// tested with `bart::Conditional`, and so is the operand of `!`, as in
+// `!done == false`.
+fn compared(expression: &token::Expression, scope_depth: u32, context: &Context) -> TokenStream {
+    match *expression {
+        token::Expression::Operand(ref argument) => operand(argument, scope_depth, context),
+        token::Expression::Not(ref inner) => {
+            let inner = compared(inner, scope_depth, context);
+            quote! { !#inner }
+        }
+        _ => {
+            let condition = condition(expression, scope_depth, context);
+            quote! { (#condition) }
+        }
+    }
+}
+
+// The value to display for an interpolation: the value of the name passed
+// through its filters and formatted according to the format spec, if any
+fn interpolated_value(
+    name: &token::Name,
+    format_spec: Option<&str>,
+    scope_depth: u32,
+    context: &Context,
+) -> TokenStream {
+    let source = context.source;
+    let span = tag_span(name, source);
+    let mut value = expression(name, scope_depth, context);
 
-    if name.function_call {
-        full_name.push_str("()");
+    for filter in &name.filters {
+        let filter_span = Span::call_site().located_at(source.span_of(filter.name));
+        let function = Ident::new(filter.name, filter_span);
+        let function = match (BUILTIN_FILTERS.contains(&filter.name), context.filters) {
+            // Public functions in the module of user-defined filters take
+            // precedence over the built-in filters, as the glob import in
+            // the inner block shadows the one in the outer block
+            (true, Some(filters)) => quote_spanned! { filter_span=>
+                ({
+                    #[allow(unused_imports)]
+                    use _bart::filters::*;
+                    {
+                        #[allow(unused_imports)]
+                        use #filters::*;
+                        #function
+                    }
+                })
+            },
+            (true, None) => quote_spanned! { filter_span=> _bart::filters::#function },
+            (false, Some(filters)) => quote_spanned! { filter_span=> #filters::#function },
+            (false, None) => function.into_token_stream(),
+        };
+        let arguments = filter
+            .arguments
+            .iter()
+            .map(|x| argument(x, scope_depth, context));
+        value = quote_spanned! { filter_span=> #function(&#value #(, #arguments)*) };
     }
 
-    syn::Ident::new(full_name)
+    match format_spec {
+        Some(format_spec) => {
+            let format_string = format!("{{:{}}}", format_spec);
+            quote_spanned! { span=> format_args!(#format_string, #value) }
+        }
+        None => value,
+    }
 }
 
 fn scope(
     name: token::Name,
     scope_level: u32,
     ast: ast::Ast,
-    partials_resolver: &mut dyn PartialsResolver,
-) -> (syn::Ident, syn::Ident, quote::Tokens) {
-    let name = resolve(&name, scope_level);
-    let scope_variable = syn::Ident::new(format!("_s{}", scope_level));
-    let nested_generated = generate(ast, scope_level + 1, partials_resolver);
+    context: &mut Context,
+) -> (TokenStream, Span, Ident, TokenStream) {
+    let span = tag_span(&name, context.source);
+    let name = expression(&name, scope_level, context);
+    let scope_variable = format_ident!("_s{}", scope_level);
+    let nested_generated = generate(ast, scope_level + 1, context);
 
-    (name, scope_variable, nested_generated)
+    (name, span, scope_variable, nested_generated)
 }
 
-pub fn generate(
-    node: ast::Ast,
+// The else branch for the else clause of a conditional section, which is in
+// the scope of the section like the section itself
+fn else_clause(
+    otherwise: Option<Box<ast::Ast>>,
+    name: &TokenStream,
     scope_level: u32,
-    partials_resolver: &mut dyn PartialsResolver,
-) -> quote::Tokens {
+    context: &mut Context,
+) -> TokenStream {
+    match otherwise {
+        Some(otherwise) => {
+            let scope_variable = format_ident!("_s{}", scope_level);
+            let otherwise = generate(*otherwise, scope_level + 1, context);
+            quote! {
+                else {
+                    let #scope_variable = &#name;
+                    #otherwise
+                }
+            }
+        }
+        None => quote! {},
+    }
+}
+
+/// The code for rendering a partial that includes itself through sections,
+/// with `nested` as the code for the partial. The partial is rendered by a
+/// helper function that calls itself, which is available to the includes of
+/// the partial within it as `_pN`, where N is the depth of the include.
+///
+/// For a partial included with named `arguments`, the helper takes the
+/// arguments struct for any lifetime, as the arguments of the includes
+/// within the partial refer to values that only live there.
+pub fn recursive_partial(
+    depth: usize,
+    arguments: Option<ArgumentsStruct>,
+    nested: TokenStream,
+) -> TokenStream {
+    let helper = format_ident!("_p{}", depth);
+    let (generics, scope) = match arguments {
+        Some(ArgumentsStruct { index, parameters }) => {
+            let name = format_ident!("_Arguments{}", index);
+            let types = (0..parameters)
+                .map(|index| format_ident!("T{}", index))
+                .collect::<Vec<_>>();
+            (
+                quote! { #(#types: ?Sized,)* },
+                quote! { #name<'_, #(#types),*> },
+            )
+        }
+        None => (quote! { T: ?Sized, }, quote! { T }),
+    };
+    quote! {
+        fn _recurse<#generics F>(
+            _s0: &#scope,
+            f: &mut ::std::fmt::Formatter,
+            body: &F,
+        ) -> ::std::fmt::Result
+        where
+            F: Fn(
+                &dyn Fn(&#scope, &mut ::std::fmt::Formatter) -> ::std::fmt::Result,
+                &#scope,
+                &mut ::std::fmt::Formatter,
+            ) -> ::std::fmt::Result,
+        {
+            body(&|_s0, f| _recurse(_s0, f, body), _s0, f)
+        }
+
+        _recurse(_s0, f, &|#helper, _s0, f| {
+            #nested
+            Ok(())
+        })?;
+    }
+}
+
+/// The code for including a partial within itself, as set up by
+/// `recursive_partial`
+pub fn recursive_include(depth: usize) -> TokenStream {
+    let helper = format_ident!("_p{}", depth);
+    quote! { #helper(_s0, f)?; }
+}
+
+pub fn generate(node: ast::Ast, scope_level: u32, context: &mut Context) -> TokenStream {
+    use ast::Ast::*;
+
+    let in_section = matches!(
+        node,
+        Iteration { .. }
+            | NegativeIteration { .. }
+            | Conditional { .. }
+            | NegativeConditional { .. }
+            | Scope {
+                otherwise: Some(_),
+                ..
+            }
+            | If { .. }
+            | Match { .. }
+    );
+    if in_section {
+        context.sections += 1;
+    }
+    let generated = generate_node(node, scope_level, context);
+    if in_section {
+        context.sections -= 1;
+    }
+    generated
+}
+
+fn generate_node(node: ast::Ast, scope_level: u32, context: &mut Context) -> TokenStream {
     use ast::Ast::*;
     match node {
         Sequence(seq) => {
             let items = seq
                 .into_iter()
-                .map(|node| generate(node, scope_level, partials_resolver));
+                .map(|node| generate(node, scope_level, context));
             quote! { #(#items)* }
         }
         Literal(text) => {
             quote! { f.write_str(#text)?; }
         }
-        Interpolation(name) => {
-            let name = resolve(&name, scope_level);
-            quote! { _bart::DisplayHtmlSafe::safe_fmt(&#name, f)?; }
+        Interpolation(name, format_spec) => {
+            let span = tag_span(&name, context.source);
+            let value = interpolated_value(&name, format_spec, scope_level, context);
+            quote_spanned! { span=> _bart::DisplayHtmlSafe::safe_fmt(&#value, f)?; }
         }
-        UnescapedInterpolation(name) => {
-            let name = resolve(&name, scope_level);
-            quote! { ::std::fmt::Display::fmt(&#name, f)?; }
+        UnescapedInterpolation(name, format_spec) => {
+            let span = tag_span(&name, context.source);
+            let value = interpolated_value(&name, format_spec, scope_level, context);
+            quote_spanned! { span=> ::std::fmt::Display::fmt(&#value, f)?; }
         }
-        Iteration { name, nested } => {
-            let (name, scope_variable, nested) =
-                scope(name, scope_level, *nested, partials_resolver);
+        Iteration {
+            name,
+            nested,
+            separator,
+            otherwise,
+        } => {
+            // The loop variables are available inside the section, but not
+            // in its own name or the else clause
+            let span = tag_span(&name, context.source);
+            let returned = name.segments.last().is_some_and(|x| x.call.is_some());
+            let name = expression(&name, scope_level, context);
+            let scope_variable = format_ident!("_s{}", scope_level);
+            context.loops.push(Loop {
+                scope_level,
+                uses_last: Cell::new(false),
+                uses_length: Cell::new(false),
+            });
+            let nested = generate(*nested, scope_level + 1, context);
+            let index = format_ident!("_i{}", scope_level);
+            // The separator is rendered before every item but the first, so
+            // the length of the iteration need not be known
+            let separator = separator.map(|separator| {
+                let separator = generate(*separator, scope_level + 1, context);
+                quote! {
+                    if #index != 0 {
+                        #separator
+                    }
+                }
+            });
+            let current = context.loops.pop().unwrap();
+
+            // A value returned from a call may be an iterator, which can only
+            // be iterated by value, as in `{{#evens()}}` returning `impl
+            // Iterator`. It is iterated by reference, so the else clause has
+            // the iterator as its scope.
+            let collection = format_ident!("_coll{}", scope_level);
+            let (value, binding, iter) = match returned {
+                true => (
+                    quote_spanned! { span=> ::std::iter::IntoIterator::into_iter(#name) },
+                    quote! { mut #collection },
+                    quote_spanned! { span=> ::std::iter::Iterator::by_ref(&mut #collection) },
+                ),
+                false => (
+                    quote! { &#name },
+                    quote! { #collection },
+                    quote_spanned! { span=> #collection.into_iter() },
+                ),
+            };
+            let iter_variable = format_ident!("_iter{}", scope_level);
+
+            let length = match current.uses_length.get() {
+                true => {
+                    let length = format_ident!("_len{}", scope_level);
+                    let len = quote_spanned! { span=>
+                        ::std::iter::ExactSizeIterator::len(&#iter_variable)
+                    };
+                    quote! { let #length = #len; }
+                }
+                false => quote! {},
+            };
+            let (peekable, last) = match current.uses_last.get() {
+                true => {
+                    let last = format_ident!("_last{}", scope_level);
+                    (
+                        quote! { .peekable() },
+                        quote! { let #last = #iter_variable.peek().is_none(); },
+                    )
+                }
+                false => (quote! {}, quote! {}),
+            };
+
+            // Whether the loop body ran is tracked while iterating, so the
+            // else clause works for iterators that can only be iterated once
+            let (empty, visited, otherwise) = match otherwise {
+                Some(otherwise) => {
+                    let empty = format_ident!("_empty{}", scope_level);
+                    let otherwise = generate(*otherwise, scope_level + 1, context);
+                    (
+                        quote! { let mut #empty = true; },
+                        quote! { #empty = false; },
+                        quote! {
+                            if #empty {
+                                let #scope_variable = #collection;
+                                #otherwise
+                            }
+                        },
+                    )
+                }
+                None => (quote! {}, quote! {}, quote! {}),
+            };
+
+            // Matching keeps temporaries in the name alive for the entire
+            // loop, as in `{{#items()}}`
             quote! {
-                for ref #scope_variable in (&#name).into_iter() {
-                    #nested
+                match #value {
+                    #binding => {
+                        let #iter_variable = #iter;
+                        #length
+                        let mut #iter_variable = #iter_variable.enumerate() #peekable;
+                        #empty
+                        while let Some((#index, #scope_variable)) = #iter_variable.next() {
+                            #visited
+                            #last
+                            #separator
+                            #nested
+                        }
+                        #otherwise
+                    }
                 }
             }
         }
         NegativeIteration { name, nested } => {
-            let (name, scope_variable, nested) =
-                scope(name, scope_level, *nested, partials_resolver);
+            let (name, span, scope_variable, nested) = scope(name, scope_level, *nested, context);
+            let iter = quote_spanned! { span=> _bart::NegativeIterator::neg_iter(&#name) };
             quote! {
-                for ref #scope_variable in _bart::NegativeIterator::neg_iter(&#name) {
+                for ref #scope_variable in #iter {
                     #nested
                 }
             }
         }
-        Conditional { name, nested } => {
-            let (name, scope_variable, nested) =
-                scope(name, scope_level, *nested, partials_resolver);
+        Conditional {
+            name,
+            nested,
+            otherwise,
+        } => {
+            let (name, span, scope_variable, nested) = scope(name, scope_level, *nested, context);
+            let otherwise = else_clause(otherwise, &name, scope_level, context);
+            let val = quote_spanned! { span=> _bart::Conditional::val(&#name) };
             quote! {
-                if _bart::Conditional::val(&#name) {
+                if #val {
                     let #scope_variable = &#name;
                     #nested
-                }
+                } #otherwise
             }
         }
-        NegativeConditional { name, nested } => {
-            let (name, scope_variable, nested) =
-                scope(name, scope_level, *nested, partials_resolver);
+        NegativeConditional {
+            name,
+            nested,
+            otherwise,
+        } => {
+            let (name, span, scope_variable, nested) = scope(name, scope_level, *nested, context);
+            let otherwise = else_clause(otherwise, &name, scope_level, context);
+            let val = quote_spanned! { span=> _bart::Conditional::val(&#name) };
             quote! {
-                if !_bart::Conditional::val(&#name) {
+                if !#val {
                     let #scope_variable = &#name;
                     #nested
-                }
+                } #otherwise
             }
         }
-        Scope { name, nested } => {
-            let (name, scope_variable, nested) =
-                scope(name, scope_level, *nested, partials_resolver);
+        Scope {
+            name,
+            nested,
+            otherwise: None,
+        } => {
+            let (name, _, scope_variable, nested) = scope(name, scope_level, *nested, context);
             quote! {
                 {
                     let #scope_variable = &#name;
@@ -118,16 +830,137 @@ pub fn generate(
                 }
             }
         }
+        // With an else clause, a scope is only entered when its value is
+        // true according to `Conditional`, like `{{#name?}}`
+        Scope {
+            name,
+            nested,
+            otherwise: Some(otherwise),
+        } => {
+            let (name, span, scope_variable, nested) = scope(name, scope_level, *nested, context);
+            let otherwise = generate(*otherwise, scope_level + 1, context);
+            let val = quote_spanned! { span=> _bart::Conditional::val(#scope_variable) };
+            quote! {
+                {
+                    let #scope_variable = &#name;
+                    if #val {
+                        #nested
+                    } else {
+                        #otherwise
+                    }
+                }
+            }
+        }
+        If {
+            branches,
+            otherwise,
+        } => {
+            let branches = branches
+                .into_iter()
+                .map(|(condition, nested)| {
+                    let condition = self::condition(&condition, scope_level, context);
+                    let nested = generate(nested, scope_level, context);
+                    quote! {
+                        if #condition {
+                            #nested
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            let otherwise = otherwise.map(|otherwise| {
+                let otherwise = generate(*otherwise, scope_level, context);
+                quote! {
+                    else {
+                        #otherwise
+                    }
+                }
+            });
+            quote! { #(#branches)else* #otherwise }
+        }
+        Match {
+            name,
+            arms,
+            otherwise,
+        } => {
+            let span = tag_span(&name, context.source);
+            let value = expression(&name, scope_level, context);
+            let scope_variable = format_ident!("_s{}", scope_level);
+
+            let mut generated_arms = vec![];
+            for (variant, nested) in arms {
+                context.arms.push(Arm {
+                    scope_level,
+                    fields: RefCell::new(vec![]),
+                });
+                let nested = generate(nested, scope_level + 1, context);
+                let arm = context.arms.pop().unwrap();
+
+                let path = match variant_path(variant, &name, context) {
+                    Ok(path) => path,
+                    Err(message) => {
+                        let error = context.source.error_at(variant, message);
+                        return quote! { #error; };
+                    }
+                };
+                let fields = arm.fields.into_inner();
+                let members = fields.iter().map(|field| member(field, span));
+                let bindings =
+                    (0..fields.len()).map(|index| format_ident!("_f{}_{}", scope_level, index));
+                generated_arms.push(quote! {
+                    #scope_variable @ #path { #(#members: #bindings,)* .. } => {
+                        #nested
+                    }
+                });
+            }
+
+            if let Some(otherwise) = otherwise {
+                let otherwise = generate(*otherwise, scope_level + 1, context);
+                generated_arms.push(quote! {
+                    #scope_variable => {
+                        #otherwise
+                    }
+                });
+            }
+
+            let value = quote_spanned! { span=> &#value };
+            quote! {
+                match #value {
+                    #(#generated_arms)*
+                }
+            }
+        }
         PartialInclude { partial_name, root } => {
-            let root = resolve(&root, scope_level);
-            let nested = partials_resolver.generate_partial(partial_name);
+            let (root, arguments) = match root {
+                token::PartialRoot::Name(root) => {
+                    let root = expression(&root, scope_level, context);
+                    (quote! { let _s0 = &#root; }, None)
+                }
+                token::PartialRoot::Arguments(arguments) => {
+                    let (root, arguments) = partial_arguments(&arguments, scope_level, context);
+                    (root, Some(arguments))
+                }
+            };
+            let nested = context.partials_resolver.generate_partial(
+                partial_name,
+                context.sections > 0,
+                arguments,
+            );
             quote! {
                 {
-                    let _s0 = &#root;
+                    #root
                     #nested
                 }
             }
         }
+        Block { nested, .. } => generate(*nested, scope_level, context),
+        Parent { .. } => panic!("Parent templates should have been flattened"),
+        Define { partial_name, .. } => {
+            let error = context.source.error_at(
+                partial_name,
+                "Partials can only be defined at the top level of a template".to_owned(),
+            );
+            quote! { #error; }
+        }
     }
 }
 
@@ -137,24 +970,80 @@ mod tests {
     use crate::scanner::name;
     use token::simple_name;
 
+    struct NoPartials;
+    impl PartialsResolver for NoPartials {
+        fn generate_partial(
+            &mut self,
+            _partial_name: &str,
+            _in_section: bool,
+            _arguments: Option<ArgumentsStruct>,
+        ) -> TokenStream {
+            unreachable!()
+        }
+    }
+
+    fn resolve_name(name: &token::Name, template: &str, scope_depth: u32) -> String {
+        let source = Source {
+            path: "test",
+            template,
+            literal: None,
+            parents: vec![],
+        };
+        let mut partials_resolver = NoPartials;
+        let arguments = RefCell::new(vec![]);
+        let context = Context::new(&source, None, &mut partials_resolver, &arguments, None);
+        resolve(name, scope_depth, &context).to_string()
+    }
+
+    fn resolve_str(template: &str, scope_depth: u32) -> String {
+        resolve_name(&name(template).unwrap().1, template, scope_depth)
+    }
+
     #[test]
     fn resolves_top_level_names() {
-        assert_eq!(resolve(&simple_name("ape"), 3), syn::Ident::new("_s0.ape"));
+        assert_eq!(resolve_name(&simple_name("ape"), "", 3), "_s0 . ape");
     }
 
     #[test]
     fn resolves_nested_names() {
+        assert_eq!(resolve_str(".ape", 3), "_s2 . ape");
+    }
+
+    #[test]
+    fn resolves_function_calls() {
+        assert_eq!(resolve_str("ape()", 3), "_s0 . ape ()");
+    }
+
+    #[test]
+    fn resolves_function_calls_with_arguments() {
         assert_eq!(
-            resolve(&name(".ape").unwrap().1, 3),
-            syn::Ident::new("_s2.ape")
+            resolve_str(".ape(\"%Y\", -1, ..katt)", 3),
+            "_s2 . ape (\"%Y\" , - 1 , & _s1 . katt)"
         );
     }
 
     #[test]
-    fn resolves_function_calls() {
+    fn resolves_function_calls_in_intermediate_segments() {
         assert_eq!(
-            resolve(&name("ape()").unwrap().1, 3),
-            syn::Ident::new("_s0.ape()")
+            resolve_str("ape().katt(1).0", 3),
+            "_s0 . ape () . katt (1) . 0"
         );
+        assert_eq!(resolve_str(".()", 3), "_s2 ()");
+    }
+
+    #[test]
+    fn resolves_tuple_fields() {
+        assert_eq!(resolve_str("ape.0", 3), "_s0 . ape . 0");
+    }
+
+    #[test]
+    fn resolves_keywords_as_raw_identifiers() {
+        assert_eq!(resolve_str("type.r#match", 3), "_s0 . r#type . r#match");
+        assert_eq!(resolve_str("r#ape", 3), "_s0 . ape");
+    }
+
+    #[test]
+    fn reports_too_many_leading_dots() {
+        assert!(resolve_str("...ape", 2).starts_with("compile_error !"));
     }
 }
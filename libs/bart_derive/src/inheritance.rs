@@ -0,0 +1,261 @@
+use crate::ast::Ast;
+use std::collections::HashMap;
+
+type Blocks<'a> = HashMap<&'a str, Ast<'a>>;
+
+/// Replace each parent template, as in {{<layout.html}}, with the syntax
+/// tree of the parent, as given by `parent`, where the blocks are overridden
+/// by those of the child. When templates further down the chain override the
+/// same block, the one furthest down takes precedence.
+pub fn flatten<'a, E>(
+    ast: Ast<'a>,
+    parent: &mut dyn FnMut(&'a str) -> Result<Ast<'a>, E>,
+) -> Result<Ast<'a>, E> {
+    flatten_with(ast, &HashMap::new(), parent)
+}
+
+fn flatten_boxed<'a, E>(
+    ast: Ast<'a>,
+    overrides: &Blocks<'a>,
+    parent: &mut dyn FnMut(&'a str) -> Result<Ast<'a>, E>,
+) -> Result<Box<Ast<'a>>, E> {
+    Ok(Box::new(flatten_with(ast, overrides, parent)?))
+}
+
+fn flatten_clause<'a, E>(
+    ast: Option<Box<Ast<'a>>>,
+    overrides: &Blocks<'a>,
+    parent: &mut dyn FnMut(&'a str) -> Result<Ast<'a>, E>,
+) -> Result<Option<Box<Ast<'a>>>, E> {
+    ast.map(|ast| flatten_boxed(*ast, overrides, parent))
+        .transpose()
+}
+
+fn flatten_with<'a, E>(
+    ast: Ast<'a>,
+    overrides: &Blocks<'a>,
+    parent: &mut dyn FnMut(&'a str) -> Result<Ast<'a>, E>,
+) -> Result<Ast<'a>, E> {
+    Ok(match ast {
+        Ast::Literal(_)
+        | Ast::Interpolation(..)
+        | Ast::UnescapedInterpolation(..)
+        | Ast::PartialInclude { .. }
+        | Ast::Define { .. } => ast,
+        Ast::Sequence(seq) => Ast::Sequence(
+            seq.into_iter()
+                .map(|node| flatten_with(node, overrides, parent))
+                .collect::<Result<_, _>>()?,
+        ),
+        Ast::Iteration {
+            name,
+            nested,
+            separator,
+            otherwise,
+        } => Ast::Iteration {
+            name,
+            nested: flatten_boxed(*nested, overrides, parent)?,
+            separator: flatten_clause(separator, overrides, parent)?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::NegativeIteration { name, nested } => Ast::NegativeIteration {
+            name,
+            nested: flatten_boxed(*nested, overrides, parent)?,
+        },
+        Ast::Conditional {
+            name,
+            nested,
+            otherwise,
+        } => Ast::Conditional {
+            name,
+            nested: flatten_boxed(*nested, overrides, parent)?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::NegativeConditional {
+            name,
+            nested,
+            otherwise,
+        } => Ast::NegativeConditional {
+            name,
+            nested: flatten_boxed(*nested, overrides, parent)?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::Scope {
+            name,
+            nested,
+            otherwise,
+        } => Ast::Scope {
+            name,
+            nested: flatten_boxed(*nested, overrides, parent)?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::If {
+            branches,
+            otherwise,
+        } => Ast::If {
+            branches: branches
+                .into_iter()
+                .map(|(condition, nested)| {
+                    Ok((condition, flatten_with(nested, overrides, parent)?))
+                })
+                .collect::<Result<_, _>>()?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::Match {
+            name,
+            arms,
+            otherwise,
+        } => Ast::Match {
+            name,
+            arms: arms
+                .into_iter()
+                .map(|(variant, nested)| Ok((variant, flatten_with(nested, overrides, parent)?)))
+                .collect::<Result<_, _>>()?,
+            otherwise: flatten_clause(otherwise, overrides, parent)?,
+        },
+        Ast::Block { name, nested } => Ast::Block {
+            name,
+            nested: match overrides.get(name) {
+                Some(overriding) => Box::new(overriding.clone()),
+                None => flatten_boxed(*nested, overrides, parent)?,
+            },
+        },
+        Ast::Parent {
+            partial_name,
+            blocks,
+        } => {
+            let mut inner = HashMap::new();
+            for (name, nested) in blocks {
+                inner.insert(name, flatten_with(nested, overrides, parent)?);
+            }
+            for (&name, nested) in overrides {
+                inner.insert(name, nested.clone());
+            }
+
+            flatten_with(parent(partial_name)?, &inner, parent)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::scanner::{sequence, DEFAULT_DELIMITERS};
+
+    fn ast(template: &str) -> Ast<'_> {
+        parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap()
+    }
+
+    fn flatten_with_parents<'a>(template: &'a str, parents: &[(&str, &'a str)]) -> Ast<'a> {
+        flatten(ast(template), &mut |partial_name| match parents
+            .iter()
+            .find(|&&(name, _)| name == partial_name)
+        {
+            Some(&(_, parent)) => Ok(ast(parent)),
+            None => Err(partial_name),
+        })
+        .unwrap()
+    }
+
+    fn block<'a>(name: &'a str, nested: Vec<Ast<'a>>) -> Ast<'a> {
+        Ast::Block {
+            name,
+            nested: Box::new(Ast::Sequence(nested)),
+        }
+    }
+
+    #[test]
+    fn it_overrides_blocks() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Sequence(vec![
+                Ast::Literal("<h1>"),
+                block("title", vec![Ast::Literal("Child")]),
+                Ast::Literal("</h1>"),
+                block("body", vec![Ast::Literal("Default")]),
+            ])]),
+            flatten_with_parents(
+                "{{<base}}{{$title}}Child{{/title}}{{/base}}",
+                &[(
+                    "base",
+                    "<h1>{{$title}}Title{{/title}}</h1>{{$body}}Default{{/body}}"
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn it_prefers_overrides_furthest_down() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Sequence(vec![Ast::Sequence(vec![
+                block("a", vec![Ast::Literal("child")]),
+                block("b", vec![Ast::Literal("middle")]),
+            ])])]),
+            flatten_with_parents(
+                "{{<middle}}{{$a}}child{{/a}}{{/middle}}",
+                &[
+                    (
+                        "middle",
+                        "{{<base}}{{$a}}middle{{/a}}{{$b}}middle{{/b}}{{/base}}"
+                    ),
+                    ("base", "{{$a}}base{{/a}}{{$b}}base{{/b}}"),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_from_parents() {
+        let res = flatten(ast("{{<missing}}{{/missing}}"), &mut |partial_name| {
+            Err::<Ast, _>(partial_name)
+        });
+        assert_eq!(Err("missing"), res);
+    }
+
+    #[test]
+    fn it_reports_cyclic_parents() {
+        let dir = std::env::temp_dir().join(format!("bart-cyclic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.html"), "{{<b.html}}{{/b.html}}").unwrap();
+        std::fs::write(dir.join("b.html"), "{{<a.html}}{{/a.html}}").unwrap();
+
+        let res = crate::load_parents(
+            "{{<a.html}}{{/a.html}}",
+            DEFAULT_DELIMITERS,
+            &dir,
+            &mut vec![],
+            &mut vec![],
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let a = dir.join("a.html").display().to_string();
+        let b = dir.join("b.html").display().to_string();
+        assert_eq!(
+            Err(format!(
+                "Cyclic template inheritance: {} -> {} -> {}",
+                a, b, a
+            )),
+            res
+        );
+    }
+
+    #[test]
+    fn it_reports_missing_parents() {
+        let dir = std::env::temp_dir().join(format!("bart-missing-{}", std::process::id()));
+        let res = crate::load_parents(
+            "{{<layout.html}}{{/layout.html}}",
+            DEFAULT_DELIMITERS,
+            &dir,
+            &mut vec![],
+            &mut vec![],
+        );
+
+        let message = res.unwrap_err();
+        let path = dir.join("layout.html").display().to_string();
+        assert!(
+            message.starts_with(&format!("Unable to read parent template {}: ", path)),
+            "{}",
+            message
+        );
+    }
+}
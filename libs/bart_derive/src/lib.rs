@@ -1,15 +1,26 @@
 mod ast;
+mod diagnostics;
 mod generator;
+mod inheritance;
 mod parser;
 mod scanner;
+mod span;
 mod token;
+mod validation;
+mod whitespace;
 
 use ast::Ast;
+use diagnostics::Diagnostic;
 use proc_macro::TokenStream;
 use quote::*;
+use span::Source;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use syn::ext::IdentExt;
+use token::{Delimiters, Token};
 
 fn user_crate_root() -> PathBuf {
     std::env::var("CARGO_MANIFEST_DIR")
@@ -17,15 +28,82 @@ fn user_crate_root() -> PathBuf {
         .into()
 }
 
-fn find_attr<'a>(attrs: &'a Vec<syn::Attribute>, name: &str) -> Option<&'a str> {
+fn find_attr(attrs: &[syn::Attribute], name: &str) -> Option<syn::LitStr> {
     attrs
         .iter()
-        .find(|&x| x.name() == name)
-        .and_then(|ref attr| match &attr.value {
-            &syn::MetaItem::NameValue(_, syn::Lit::Str(ref template, _)) => Some(template),
+        .find(|&x| x.path().is_ident(name))
+        .and_then(|attr| match attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(ref value),
+                        ..
+                    }),
+                ..
+            }) => Some(value.clone()),
             _ => None,
         })
-        .map(|x| x.as_ref())
+}
+
+fn has_attr(attrs: &[syn::Attribute], name: &str) -> bool {
+    attrs.iter().any(|x| x.path().is_ident(name))
+}
+
+// The names of the fields of a struct, or None if the input is not a struct
+fn struct_fields(ast: &syn::DeriveInput) -> Option<Vec<String>> {
+    match ast.data {
+        syn::Data::Struct(ref data) => Some(
+            data.fields
+                .iter()
+                .enumerate()
+                .map(|(index, field)| match field.ident {
+                    Some(ref ident) => ident.unraw().to_string(),
+                    None => index.to_string(),
+                })
+                .collect(),
+        ),
+        syn::Data::Enum(_) | syn::Data::Union(_) => None,
+    }
+}
+
+// The types of the fields of a struct, for naming the variants of enums in
+// match sections, as in `Status` for `status: &'a Status<'a>`
+fn field_types(ast: &syn::DeriveInput) -> HashMap<String, syn::Path> {
+    fn type_path(field_type: &syn::Type) -> Option<syn::Path> {
+        match *field_type {
+            syn::Type::Reference(ref reference) => type_path(&reference.elem),
+            syn::Type::Paren(ref paren) => type_path(&paren.elem),
+            syn::Type::Group(ref group) => type_path(&group.elem),
+            syn::Type::Path(syn::TypePath {
+                qself: None,
+                ref path,
+            }) => {
+                let mut path = path.clone();
+                // Generic arguments are inferred in patterns
+                if let Some(last) = path.segments.last_mut() {
+                    last.arguments = syn::PathArguments::None;
+                }
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+
+    match ast.data {
+        syn::Data::Struct(ref data) => data
+            .fields
+            .iter()
+            .enumerate()
+            .filter_map(|(index, field)| {
+                let name = match field.ident {
+                    Some(ref ident) => ident.unraw().to_string(),
+                    None => index.to_string(),
+                };
+                type_path(&field.ty).map(|path| (name, path))
+            })
+            .collect(),
+        syn::Data::Enum(_) | syn::Data::Union(_) => HashMap::new(),
+    }
 }
 
 fn buf_file<P: AsRef<Path>>(filename: P) -> String {
@@ -36,92 +114,619 @@ fn buf_file<P: AsRef<Path>>(filename: P) -> String {
     buf
 }
 
-fn parse_str(input: &str) -> Result<Ast<'_>, parser::Error<'_>> {
-    parser::parse(scanner::sequence(input).unwrap())
+fn parse_str<'a>(
+    input: &'a str,
+    delimiters: Delimiters<'a>,
+    strip_standalone_lines: bool,
+) -> Result<Ast<'a>, Diagnostic> {
+    let tokens = scanner::sequence(input, delimiters).map_err(Diagnostic::from_scanner_error)?;
+    let mut tokens = whitespace::trim_marked_whitespace(tokens);
+
+    if strip_standalone_lines {
+        tokens = whitespace::strip_standalone_lines(tokens);
+    }
+
+    // Comments and delimiter changes never reach the output, so the parser
+    // need not know about them
+    parser::parse(
+        tokens
+            .into_iter()
+            .filter(|token| !matches!(token, Token::Comment(_) | Token::SetDelimiters(_))),
+    )
+    .map_err(|err| Diagnostic::from_parser_error(input, err))
+}
+
+// The path to show in error messages for a template file
+fn display_path(path: &Path) -> String {
+    let crate_root = user_crate_root();
+    path.strip_prefix(&crate_root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
 }
 
-struct InlinePartialsResolver;
-impl generator::PartialsResolver for InlinePartialsResolver {
-    fn generate_partial(&mut self, _partial_name: &str) -> quote::Tokens {
-        panic!("Partials are unavailable when using template_string");
+// The file that the name of a partial or parent template refers to. Names
+// starting with `/` are relative to the crate root.
+fn partial_path(base_dir: &Path, partial_name: &str) -> PathBuf {
+    let relative_path: PathBuf = partial_name.into();
+    match relative_path.has_root() {
+        true => user_crate_root().join(relative_path.strip_prefix("/").unwrap()),
+        false => base_dir.join(partial_name),
+    }
+}
+
+// A parent template, as in {{<layout.html}}. Parent templates are loaded
+// before the template is parsed, so the flattened syntax tree can borrow
+// from them.
+struct ParentTemplate {
+    abs_path: PathBuf,
+    path: String,
+    template: String,
+}
+
+// Load the parent templates of a template, and their parents in turn.
+// `stack` holds the paths of the templates being loaded, to detect cycles.
+fn load_parents(
+    template: &str,
+    delimiters: Delimiters,
+    base_dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    parents: &mut Vec<ParentTemplate>,
+) -> Result<(), String> {
+    // Syntax errors are reported when the template is parsed
+    let tokens = scanner::sequence(template, delimiters).unwrap_or_default();
+
+    for token in tokens {
+        let abs_path = match token {
+            Token::Parent(name) => partial_path(base_dir, name.text),
+            _ => continue,
+        };
+
+        if let Some(start) = stack.iter().position(|x| *x == abs_path) {
+            let cycle: Vec<_> = stack[start..]
+                .iter()
+                .chain(Some(&abs_path))
+                .map(|x| display_path(x))
+                .collect();
+            return Err(format!(
+                "Cyclic template inheritance: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        if parents.iter().any(|x| x.abs_path == abs_path) {
+            continue;
+        }
+
+        let template = std::fs::read_to_string(&abs_path).map_err(|e| {
+            format!(
+                "Unable to read parent template {}: {}",
+                display_path(&abs_path),
+                e
+            )
+        })?;
+        let parent_dir = abs_path.parent().unwrap().to_owned();
+        stack.push(abs_path);
+        load_parents(
+            &template,
+            scanner::DEFAULT_DELIMITERS,
+            &parent_dir,
+            stack,
+            parents,
+        )?;
+        let abs_path = stack.pop().unwrap();
+
+        parents.push(ParentTemplate {
+            path: display_path(&abs_path),
+            abs_path,
+            template,
+        });
+    }
+
+    Ok(())
+}
+
+fn parent_sources(parents: &[ParentTemplate]) -> Vec<Source<'_>> {
+    parents
+        .iter()
+        .map(|parent| Source {
+            path: &parent.path,
+            template: &parent.template,
+            literal: None,
+            parents: vec![],
+        })
+        .collect()
+}
+
+// Replace the parent templates in the syntax tree by the parent templates,
+// with the blocks overridden. The parents must have been loaded by
+// `load_parents`, and be the parents of `source`, in the same order.
+fn flatten<'a>(
+    ast: Ast<'a>,
+    source: &Source<'a>,
+    base_dir: &Path,
+    parents: &'a [ParentTemplate],
+    strip_standalone_lines: bool,
+) -> Result<Ast<'a>, proc_macro2::TokenStream> {
+    inheritance::flatten(ast, &mut |partial_name| {
+        // Parent templates are found relative to the template naming them
+        let base_dir = match source.parents.iter().position(|x| x.contains(partial_name)) {
+            Some(index) => parents[index].abs_path.parent().unwrap(),
+            None => base_dir,
+        };
+        let abs_path = partial_path(base_dir, partial_name);
+
+        let index = parents
+            .iter()
+            .position(|x| x.abs_path == abs_path)
+            .expect("Parent templates should have been loaded");
+        parse_str(
+            &parents[index].template,
+            scanner::DEFAULT_DELIMITERS,
+            strip_standalone_lines,
+        )
+        .map_err(|diagnostic| source.parents[index].compile_error(&diagnostic))
+    })
+}
+
+// A partial that is being generated, for detecting partials that include
+// themselves
+struct Include {
+    // The path of a partial file, or the template and name of a partial
+    // definition
+    key: String,
+    partial_name: String,
+
+    // Whether the include is within a section of the including template
+    in_section: bool,
+
+    // The struct of the scope, when the partial is included with named
+    // arguments
+    arguments: Option<generator::ArgumentsStruct>,
+
+    // Whether the partial includes itself, so it is rendered by a helper
+    // function
+    recursive: bool,
+}
+
+// Generate the code for a partial with `generate`, unless the partial is
+// already being generated. A partial that includes itself through a section
+// is rendered by a helper function that calls itself, while one that always
+// includes itself is an error. So is a partial that includes itself with
+// other named arguments, as the helper takes a scope of a single type.
+fn include_partial(
+    includes: &RefCell<Vec<Include>>,
+    key: String,
+    partial_name: &str,
+    in_section: bool,
+    arguments: Option<generator::ArgumentsStruct>,
+    generate: impl FnOnce() -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let mut stack = includes.borrow_mut();
+    if let Some(depth) = stack.iter().position(|x| x.key == key) {
+        let cycle = &stack[depth..];
+        let names = cycle
+            .iter()
+            .map(|x| x.partial_name.as_str())
+            .chain(std::iter::once(partial_name))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        if !in_section && cycle[1..].iter().all(|x| !x.in_section) {
+            let message = format!("Recursive partial inclusion: {}", names);
+            return quote! { compile_error!(#message); };
+        }
+        if cycle[0].arguments != arguments {
+            let message = format!(
+                "Recursive partial inclusion with other named arguments: {}",
+                names
+            );
+            return quote! { compile_error!(#message); };
+        }
+
+        stack[depth].recursive = true;
+        return generator::recursive_include(depth);
+    }
+
+    let depth = stack.len();
+    stack.push(Include {
+        key,
+        partial_name: partial_name.to_owned(),
+        in_section,
+        arguments,
+        recursive: false,
+    });
+    drop(stack);
+
+    let nested = generate();
+    match includes.borrow_mut().pop() {
+        Some(Include {
+            recursive: true, ..
+        }) => generator::recursive_partial(depth, arguments, nested),
+        _ => nested,
     }
 }
 
 struct FilesystemPartialsResolver<'a> {
     base_dir: PathBuf,
     dependencies: &'a mut Vec<String>,
+    includes: &'a RefCell<Vec<Include>>,
+    arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+    strip_standalone_lines: bool,
+    filters: Option<&'a syn::Path>,
 }
 
 impl<'a> FilesystemPartialsResolver<'a> {
     fn new<T: Into<PathBuf>>(
         base_dir: T,
-        dependencies: &mut Vec<String>,
-    ) -> FilesystemPartialsResolver<'_> {
+        dependencies: &'a mut Vec<String>,
+        includes: &'a RefCell<Vec<Include>>,
+        arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+        strip_standalone_lines: bool,
+        filters: Option<&'a syn::Path>,
+    ) -> FilesystemPartialsResolver<'a> {
         FilesystemPartialsResolver {
             base_dir: base_dir.into(),
             dependencies,
+            includes,
+            arguments_structs,
+            strip_standalone_lines,
+            filters,
         }
     }
-}
 
-impl<'a> generator::PartialsResolver for FilesystemPartialsResolver<'a> {
-    fn generate_partial(&mut self, partial_name: &str) -> quote::Tokens {
-        let relative_path: PathBuf = partial_name.into();
-        let abs_path = match relative_path.has_root() {
-            true => user_crate_root().join(relative_path.strip_prefix("/").unwrap()),
-            false => self.base_dir.join(partial_name),
-        };
+    fn generate_file(&mut self, abs_path: PathBuf) -> proc_macro2::TokenStream {
         self.dependencies
             .push(abs_path.to_str().unwrap().to_owned());
         let template = buf_file(&abs_path);
-        let parsed = parse_str(&template).unwrap();
-        let nested_resolver =
-            &mut FilesystemPartialsResolver::new(abs_path.parent().unwrap(), self.dependencies);
-        generator::generate(parsed, 1, nested_resolver)
+        let path = display_path(&abs_path);
+
+        let mut parents = vec![];
+        let base_dir = abs_path.parent().unwrap();
+        let inheritance = load_parents(
+            &template,
+            scanner::DEFAULT_DELIMITERS,
+            base_dir,
+            &mut vec![abs_path.clone()],
+            &mut parents,
+        );
+        self.dependencies.extend(
+            parents
+                .iter()
+                .map(|parent| parent.abs_path.to_str().unwrap().to_owned()),
+        );
+        if let Err(message) = inheritance {
+            return quote! { compile_error!(#message); };
+        }
+
+        let source = Source {
+            path: &path,
+            template: &template,
+            literal: None,
+            parents: parent_sources(&parents),
+        };
+        let parsed = match parse_str(
+            &template,
+            scanner::DEFAULT_DELIMITERS,
+            self.strip_standalone_lines,
+        ) {
+            Ok(parsed) => parsed,
+            Err(diagnostic) => {
+                let error = source.compile_error(&diagnostic);
+                return quote! { #error; };
+            }
+        };
+        let parsed = match flatten(
+            parsed,
+            &source,
+            base_dir,
+            &parents,
+            self.strip_standalone_lines,
+        ) {
+            Ok(parsed) => parsed,
+            Err(error) => return quote! { #error; },
+        };
+        let mut nested_resolver = FilesystemPartialsResolver::new(
+            abs_path.parent().unwrap(),
+            self.dependencies,
+            self.includes,
+            self.arguments_structs,
+            self.strip_standalone_lines,
+            self.filters,
+        );
+        generate_template(
+            parsed,
+            &source,
+            self.filters,
+            &mut nested_resolver,
+            self.includes,
+            self.arguments_structs,
+            None,
+        )
+    }
+}
+
+impl<'a> generator::PartialsResolver for FilesystemPartialsResolver<'a> {
+    fn generate_partial(
+        &mut self,
+        partial_name: &str,
+        in_section: bool,
+        arguments: Option<generator::ArgumentsStruct>,
+    ) -> proc_macro2::TokenStream {
+        let abs_path = partial_path(&self.base_dir, partial_name);
+        let key = abs_path.to_str().unwrap().to_owned();
+        include_partial(
+            self.includes,
+            key,
+            partial_name,
+            in_section,
+            arguments,
+            || self.generate_file(abs_path),
+        )
+    }
+}
+
+// Remove the partial definitions, as in {{*define row}}...{{/define}}, from
+// the top level of the syntax tree. Definitions in parent templates end up
+// in nested sequences when the tree is flattened.
+fn take_definitions<'a>(ast: Ast<'a>, definitions: &mut Vec<(&'a str, Ast<'a>)>) -> Ast<'a> {
+    match ast {
+        Ast::Sequence(seq) => Ast::Sequence(
+            seq.into_iter()
+                .map(|node| take_definitions(node, definitions))
+                .collect(),
+        ),
+        Ast::Define {
+            partial_name,
+            nested,
+        } => {
+            definitions.push((partial_name, *nested));
+            Ast::Sequence(vec![])
+        }
+        ast => ast,
     }
 }
 
-#[proc_macro_derive(BartDisplay, attributes(template, template_string, template_root))]
+// Partials defined in the template, which take precedence over the partials
+// found by `fallback`
+struct DefinedPartialsResolver<'a> {
+    definitions: Vec<(&'a str, Ast<'a>)>,
+    source: &'a Source<'a>,
+    filters: Option<&'a syn::Path>,
+    fallback: &'a mut dyn generator::PartialsResolver,
+    includes: &'a RefCell<Vec<Include>>,
+    arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+}
+
+impl<'a> generator::PartialsResolver for DefinedPartialsResolver<'a> {
+    fn generate_partial(
+        &mut self,
+        partial_name: &str,
+        in_section: bool,
+        arguments: Option<generator::ArgumentsStruct>,
+    ) -> proc_macro2::TokenStream {
+        // The last definition of a name wins, as in the parent templates
+        let nested = match self
+            .definitions
+            .iter()
+            .rev()
+            .find(|&&(name, _)| name == partial_name)
+        {
+            Some((_, nested)) => nested.clone(),
+            None => {
+                return self
+                    .fallback
+                    .generate_partial(partial_name, in_section, arguments)
+            }
+        };
+
+        let (source, filters, structs) = (self.source, self.filters, self.arguments_structs);
+        let key = format!("{}#{}", source.path, partial_name);
+        include_partial(
+            self.includes,
+            key,
+            partial_name,
+            in_section,
+            arguments,
+            || {
+                let mut context = generator::Context::new(source, filters, self, structs, None);
+                generator::generate(nested, 1, &mut context)
+            },
+        )
+    }
+}
+
+// Generate the code for a parsed and flattened template, resolving partials
+// by the definitions in the template before `partials_resolver`
+fn generate_template<'a>(
+    ast: Ast<'a>,
+    source: &'a Source<'a>,
+    filters: Option<&'a syn::Path>,
+    partials_resolver: &'a mut dyn generator::PartialsResolver,
+    includes: &'a RefCell<Vec<Include>>,
+    arguments_structs: &'a RefCell<Vec<Vec<String>>>,
+    field_types: Option<&'a HashMap<String, syn::Path>>,
+) -> proc_macro2::TokenStream {
+    let mut definitions = vec![];
+    let ast = take_definitions(ast, &mut definitions);
+    let mut partials_resolver = DefinedPartialsResolver {
+        definitions,
+        source,
+        filters,
+        fallback: partials_resolver,
+        includes,
+        arguments_structs,
+    };
+    let mut context = generator::Context::new(
+        source,
+        filters,
+        &mut partials_resolver,
+        arguments_structs,
+        field_types,
+    );
+    generator::generate(ast, 1, &mut context)
+}
+
+#[proc_macro_derive(
+    BartDisplay,
+    attributes(
+        template,
+        template_string,
+        template_root,
+        template_delimiters,
+        template_keep_standalone_lines,
+        template_filters,
+        template_partials_dir,
+        template_skip_field_check
+    )
+)]
 pub fn bart_display(input: TokenStream) -> TokenStream {
-    let s = input.to_string();
-    let ast = syn::parse_macro_input(&s).unwrap();
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
 
     let mut dependencies = Vec::<String>::new();
+    let strip_standalone_lines = !has_attr(&ast.attrs, "template_keep_standalone_lines");
+    let filters = find_attr(&ast.attrs, "template_filters").map(|x| {
+        x.parse::<syn::Path>()
+            .expect("Syntax error in template_filters")
+    });
+
+    let delimiters_attr = find_attr(&ast.attrs, "template_delimiters").map(|x| x.value());
+    let delimiters = delimiters_attr
+        .as_deref()
+        .map(|x| scanner::delimiters(x).expect("Syntax error in template_delimiters"))
+        .unwrap_or(scanner::DEFAULT_DELIMITERS);
 
+    let partials_dir = find_attr(&ast.attrs, "template_partials_dir").map(|x| x.value());
+
+    let mut parents = vec![];
+    let includes = RefCell::new(vec![]);
+    let arguments_structs = RefCell::new(vec![]);
     let generated = {
-        let (template, mut partials_resolver): (_, Box<dyn generator::PartialsResolver>) =
+        let (template, template_path, template_literal, template_file) =
             match find_attr(&ast.attrs, "template") {
                 Some(filename) => {
-                    let abs_filename = user_crate_root().join(filename);
+                    let filename = filename.value();
+                    let abs_filename = user_crate_root().join(&filename);
                     dependencies.push(abs_filename.to_str().unwrap().to_owned());
-                    let resolver = FilesystemPartialsResolver::new(
-                        abs_filename.parent().unwrap(),
-                        &mut dependencies,
-                    );
-                    (buf_file(&abs_filename), Box::new(resolver))
+                    (buf_file(&abs_filename), filename, None, Some(abs_filename))
                 }
                 None => {
-                    let template = find_attr(&ast.attrs, "template_string")
-                        .map(|x| x.to_owned())
-                        .expect(
-                            "#[derive(BartDisplay)] requires #[template = \"(filename)\"] \
-                            or  #[template_string = \"...\"]",
-                        );
-                    (template, Box::new(InlinePartialsResolver))
+                    let template = find_attr(&ast.attrs, "template_string").expect(
+                        "#[derive(BartDisplay)] requires #[template = \"(filename)\"] \
+                        or  #[template_string = \"...\"]",
+                    );
+                    (
+                        template.value(),
+                        "template_string".to_owned(),
+                        Some(template.token()),
+                        None,
+                    )
                 }
             };
 
-        let parsed = parse_str(&template).unwrap();
-        generator::generate(parsed, 1, &mut *partials_resolver)
-    };
+        // Partials and parent templates are found relative to the template
+        // file, unless another directory is given. Inline templates have no
+        // directory, so they use the crate root.
+        let base_dir = match (&partials_dir, &template_file) {
+            (Some(partials_dir), _) => user_crate_root().join(partials_dir),
+            (None, Some(abs_filename)) => abs_filename.parent().unwrap().to_owned(),
+            (None, None) => user_crate_root(),
+        };
+        let inheritance = load_parents(
+            &template,
+            delimiters,
+            &base_dir,
+            &mut template_file.iter().cloned().collect(),
+            &mut parents,
+        );
+        let mut partials_resolver = FilesystemPartialsResolver::new(
+            &base_dir,
+            &mut dependencies,
+            &includes,
+            &arguments_structs,
+            strip_standalone_lines,
+            filters.as_ref(),
+        );
+
+        // Errors in an inline template are located in the string literal
+        let source = Source {
+            path: &template_path,
+            template: &template,
+            literal: template_literal,
+            parents: parent_sources(&parents),
+        };
 
-    let template_root = syn::Ident::new(
-        find_attr(&ast.attrs, "template_root")
-            .map(|x| scanner::segmented_name(&x).expect("Syntax error in template_root"))
-            .map(|x| format!("self.{}", x.join(".")))
-            .unwrap_or("self".to_owned()),
+        // Names in the template can only be checked against the fields
+        // when the template root is the struct itself
+        let (fields, field_types) = match find_attr(&ast.attrs, "template_root") {
+            Some(_) => (None, None),
+            None => (struct_fields(&ast), Some(field_types(&ast))),
+        };
+        let fields = fields.filter(|_| !has_attr(&ast.attrs, "template_skip_field_check"));
+
+        let diagnostics = match (
+            inheritance,
+            parse_str(&template, delimiters, strip_standalone_lines),
+        ) {
+            (Err(message), _) => Ok(quote! { compile_error!(#message); }),
+            (Ok(()), Ok(parsed)) => {
+                // Parent templates are left for rustc to check, like partials
+                let diagnostics = fields
+                    .map(|fields| {
+                        validation::check_fields(
+                            &template,
+                            &parsed,
+                            &ast.ident.to_string(),
+                            &fields,
+                        )
+                    })
+                    .unwrap_or_default();
+
+                if diagnostics.is_empty() {
+                    match flatten(parsed, &source, &base_dir, &parents, strip_standalone_lines) {
+                        Ok(parsed) => Ok(generate_template(
+                            parsed,
+                            &source,
+                            filters.as_ref(),
+                            &mut partials_resolver,
+                            &includes,
+                            &arguments_structs,
+                            field_types.as_ref(),
+                        )),
+                        Err(error) => Ok(quote! { #error; }),
+                    }
+                } else {
+                    Err(diagnostics)
+                }
+            }
+            (Ok(()), Err(diagnostic)) => Err(vec![diagnostic]),
+        };
+
+        match diagnostics {
+            Ok(generated) => generated,
+            Err(diagnostics) => {
+                let errors = diagnostics
+                    .iter()
+                    .map(|diagnostic| source.compile_error(diagnostic));
+                quote! { #(#errors;)* }
+            }
+        }
+    };
+    dependencies.extend(
+        parents
+            .iter()
+            .map(|parent| parent.abs_path.to_str().unwrap().to_owned()),
     );
 
+    let template_root = match find_attr(&ast.attrs, "template_root") {
+        Some(root) => {
+            let value = root.value();
+            let segments = scanner::segmented_name(&value).expect("Syntax error in template_root");
+            generator::field_path(quote! { self }, &segments, root.span())
+        }
+        None => quote! { self },
+    };
+
+    let arguments_structs = generator::arguments_structs(&arguments_structs.borrow());
+
     let name = &ast.ident;
     let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
 
@@ -130,6 +735,8 @@ pub fn bart_display(input: TokenStream) -> TokenStream {
         const _: () = {
             extern crate bart as _bart;
 
+            #arguments_structs
+
             #[automatically_derived]
             impl #impl_generics ::std::fmt::Display for #name #ty_generics #where_clause {
                 fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
@@ -147,5 +754,88 @@ pub fn bart_display(input: TokenStream) -> TokenStream {
         };
     };
 
-    gen.parse().unwrap()
+    gen.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn include<'a>(
+        includes: &'a RefCell<Vec<Include>>,
+        partial_name: &'a str,
+        in_section: bool,
+        generate: impl FnOnce() -> proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        include_partial(
+            includes,
+            partial_name.to_owned(),
+            partial_name,
+            in_section,
+            None,
+            generate,
+        )
+    }
+
+    #[test]
+    fn it_rejects_recursion_outside_sections() {
+        let includes = RefCell::new(vec![]);
+        let generated = include(&includes, "a", false, || {
+            include(&includes, "b", false, || {
+                include(&includes, "a", false, || unreachable!())
+            })
+        });
+
+        assert_eq!(
+            quote! { compile_error!("Recursive partial inclusion: a -> b -> a"); }.to_string(),
+            generated.to_string()
+        );
+        assert!(includes.borrow().is_empty());
+    }
+
+    #[test]
+    fn it_renders_recursion_through_sections_with_helper() {
+        let includes = RefCell::new(vec![]);
+        let generated = include(&includes, "a", false, || {
+            include(&includes, "b", true, || {
+                include(&includes, "a", false, || unreachable!())
+            })
+        });
+
+        assert_eq!(
+            generator::recursive_partial(0, None, generator::recursive_include(0)).to_string(),
+            generated.to_string()
+        );
+        assert!(includes.borrow().is_empty());
+    }
+
+    #[test]
+    fn it_rejects_recursion_with_other_arguments() {
+        let includes = RefCell::new(vec![]);
+        let arguments = |index| {
+            Some(generator::ArgumentsStruct {
+                index,
+                parameters: 1,
+            })
+        };
+        let generated =
+            include_partial(&includes, "a".to_owned(), "a", false, arguments(0), || {
+                include_partial(
+                    &includes,
+                    "a".to_owned(),
+                    "a",
+                    true,
+                    arguments(1),
+                    || unreachable!(),
+                )
+            });
+
+        assert_eq!(
+            quote! {
+                compile_error!("Recursive partial inclusion with other named arguments: a -> a");
+            }
+            .to_string(),
+            generated.to_string()
+        );
+    }
 }
@@ -3,12 +3,212 @@ use crate::token::*;
 use std::iter::*;
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum Error<'a> {
     Mismatch {
         expected: &'static str,
-        found: Option<Token<'a>>,
+        found: Option<Box<Token<'a>>>,
     },
+    UnclosedSection {
+        opener: Name<'a>,
+    },
+    MismatchedSectionCloser {
+        opener: Box<Name<'a>>,
+        closer: Box<Name<'a>>,
+    },
+    StraySectionCloser {
+        closer: Name<'a>,
+    },
+}
+
+// Consume the section closer matching the opener, which is given back
+fn section_closer<'a, T>(
+    token_stream: &mut Peekable<T>,
+    opener: Name<'a>,
+) -> Result<Name<'a>, Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    match token_stream.next() {
+        Some(Token::SectionCloser(ref close_name)) if close_name.refers_to(&opener) => Ok(opener),
+        Some(Token::SectionCloser(closer)) => Err(Error::MismatchedSectionCloser {
+            opener: Box::new(opener),
+            closer: Box::new(closer),
+        }),
+        None => Err(Error::UnclosedSection { opener }),
+        x => Err(Error::Mismatch {
+            expected: "section closer",
+            found: x.map(Box::new),
+        }),
+    }
+}
+
+fn if_section<'a, T>(token_stream: &mut Peekable<T>) -> Result<Ast<'a>, Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    let (opener, condition) = match token_stream.next() {
+        Some(Token::If(opener, condition)) => Ok((opener, condition)),
+        x => Err(Error::Mismatch {
+            expected: "if section",
+            found: x.map(Box::new),
+        }),
+    }?;
+
+    let mut branches = vec![(*condition, sequence(token_stream)?)];
+
+    while let Some(&Token::ElseIf(..)) = token_stream.peek() {
+        match token_stream.next() {
+            Some(Token::ElseIf(_, condition)) => {
+                branches.push((*condition, sequence(token_stream)?))
+            }
+            _ => panic!("Outer match should guarantee match in inner match"),
+        }
+    }
+
+    let otherwise = match token_stream.peek() {
+        Some(&Token::Else(_)) => {
+            token_stream.next();
+            Some(Box::new(sequence(token_stream)?))
+        }
+        _ => None,
+    };
+
+    section_closer(token_stream, opener)?;
+
+    Ok(Ast::If {
+        branches,
+        otherwise,
+    })
+}
+
+fn match_section<'a, T>(token_stream: &mut Peekable<T>) -> Result<Ast<'a>, Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    let (opener, name) = match token_stream.next() {
+        Some(Token::Match(opener, name)) => Ok((opener, name)),
+        x => Err(Error::Mismatch {
+            expected: "match section",
+            found: x.map(Box::new),
+        }),
+    }?;
+
+    // Only whitespace is allowed before the first arm
+    while let Some(&Token::Literal(text)) = token_stream.peek() {
+        if !text.trim().is_empty() {
+            break;
+        }
+        token_stream.next();
+    }
+
+    let mut arms = vec![];
+    while let Some(&Token::Arm(variant)) = token_stream.peek() {
+        token_stream.next();
+        arms.push((variant, sequence(token_stream)?));
+    }
+
+    if arms.is_empty() {
+        return Err(Error::Mismatch {
+            expected: "match arm",
+            found: token_stream.next().map(Box::new),
+        });
+    }
+
+    let otherwise = match token_stream.peek() {
+        Some(&Token::Else(_)) => {
+            token_stream.next();
+            Some(Box::new(sequence(token_stream)?))
+        }
+        _ => None,
+    };
+
+    section_closer(token_stream, opener)?;
+
+    Ok(Ast::Match {
+        name: *name,
+        arms,
+        otherwise,
+    })
+}
+
+// A block, as in {{$content}}...{{/content}}, with its name and content
+fn block<'a, T>(token_stream: &mut Peekable<T>) -> Result<(&'a str, Ast<'a>), Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    let opener = match token_stream.next() {
+        Some(Token::Block(opener)) => Ok(opener),
+        x => Err(Error::Mismatch {
+            expected: "block",
+            found: x.map(Box::new),
+        }),
+    }?;
+
+    let nested = sequence(token_stream)?;
+    let opener = section_closer(token_stream, opener)?;
+
+    Ok((opener.text, nested))
+}
+
+fn parent<'a, T>(token_stream: &mut Peekable<T>) -> Result<Ast<'a>, Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    let opener = match token_stream.next() {
+        Some(Token::Parent(opener)) => Ok(opener),
+        x => Err(Error::Mismatch {
+            expected: "parent template",
+            found: x.map(Box::new),
+        }),
+    }?;
+
+    // Only blocks overriding those of the parent template, separated by
+    // whitespace, are allowed
+    let mut blocks = vec![];
+    loop {
+        match token_stream.peek() {
+            Some(&Token::Literal(text)) if text.trim().is_empty() => {
+                token_stream.next();
+            }
+            Some(&Token::Block(_)) => blocks.push(block(token_stream)?),
+            Some(&Token::SectionCloser(_)) | None => break,
+            _ => {
+                return Err(Error::Mismatch {
+                    expected: "block",
+                    found: token_stream.next().map(Box::new),
+                })
+            }
+        }
+    }
+
+    let opener = section_closer(token_stream, opener)?;
+
+    Ok(Ast::Parent {
+        partial_name: opener.text,
+        blocks,
+    })
+}
+
+// A partial definition, as in {{*define row}}...{{/define}}
+fn define<'a, T>(token_stream: &mut Peekable<T>) -> Result<Ast<'a>, Error<'a>>
+where
+    T: Iterator<Item = Token<'a>>,
+{
+    let (keyword, partial_name) = match token_stream.next() {
+        Some(Token::Define(keyword, partial_name)) => Ok((keyword, partial_name)),
+        x => Err(Error::Mismatch {
+            expected: "partial definition",
+            found: x.map(Box::new),
+        }),
+    }?;
+
+    let nested = Box::new(sequence(token_stream)?);
+    section_closer(token_stream, keyword)?;
+
+    Ok(Ast::Define {
+        partial_name,
+        nested,
+    })
 }
 
 fn section<'a, T>(token_stream: &mut Peekable<T>) -> Result<Ast<'a>, Error<'a>>
@@ -19,40 +219,53 @@ where
         Some(Token::SectionOpener(section_type, name)) => Ok((section_type, name)),
         x => Err(Error::Mismatch {
             expected: "section opener",
-            found: x,
+            found: x.map(Box::new),
         }),
     }?;
 
     let nested = Box::new(sequence(token_stream)?);
 
-    match token_stream.next() {
-        Some(Token::SectionCloser(ref close_name)) if close_name == &name => Ok(()),
-        x => Err(Error::Mismatch {
-            expected: "section closer",
-            found: x,
-        }),
-    }?;
+    let separator = match (&section_type, token_stream.peek()) {
+        (SectionType::Iteration, Some(Token::Separator(_))) => {
+            token_stream.next();
+            Some(Box::new(sequence(token_stream)?))
+        }
+        _ => None,
+    };
+
+    let otherwise = match (&section_type, token_stream.peek()) {
+        (SectionType::NegativeIteration, _) => None,
+        (_, Some(Token::Else(_))) => {
+            token_stream.next();
+            Some(Box::new(sequence(token_stream)?))
+        }
+        _ => None,
+    };
+
+    let name = section_closer(token_stream, name)?;
 
     Ok(match section_type {
         SectionType::Iteration => Ast::Iteration {
-            name: name,
-            nested: nested,
-        },
-        SectionType::NegativeIteration => Ast::NegativeIteration {
-            name: name,
-            nested: nested,
+            name,
+            nested,
+            separator,
+            otherwise,
         },
+        SectionType::NegativeIteration => Ast::NegativeIteration { name, nested },
         SectionType::Conditional => Ast::Conditional {
-            name: name,
-            nested: nested,
+            name,
+            nested,
+            otherwise,
         },
         SectionType::NegativeConditional => Ast::NegativeConditional {
-            name: name,
-            nested: nested,
+            name,
+            nested,
+            otherwise,
         },
         SectionType::Scope => Ast::Scope {
-            name: name,
-            nested: nested,
+            name,
+            nested,
+            otherwise,
         },
     })
 }
@@ -69,15 +282,30 @@ where
                 token_stream.next();
                 Ast::Literal(text)
             }
-            Some(&Token::Interpolation(_)) => match token_stream.next() {
-                Some(Token::Interpolation(name)) => Ast::Interpolation(name),
+            Some(&Token::Interpolation(..)) => match token_stream.next() {
+                Some(Token::Interpolation(name, format_spec)) => {
+                    Ast::Interpolation(name, format_spec)
+                }
                 _ => panic!("Outer match should guarantee match in inner match"),
             },
-            Some(&Token::UnescapedInterpolation(_)) => match token_stream.next() {
-                Some(Token::UnescapedInterpolation(name)) => Ast::UnescapedInterpolation(name),
+            Some(&Token::UnescapedInterpolation(..)) => match token_stream.next() {
+                Some(Token::UnescapedInterpolation(name, format_spec)) => {
+                    Ast::UnescapedInterpolation(name, format_spec)
+                }
                 _ => panic!("Outer match should guarantee match in inner match"),
             },
             Some(&Token::SectionOpener(..)) => section(token_stream)?,
+            Some(&Token::If(..)) => if_section(token_stream)?,
+            Some(&Token::Match(..)) => match_section(token_stream)?,
+            Some(&Token::Block(..)) => {
+                let (name, nested) = block(token_stream)?;
+                Ast::Block {
+                    name,
+                    nested: Box::new(nested),
+                }
+            }
+            Some(&Token::Parent(..)) => parent(token_stream)?,
+            Some(&Token::Define(..)) => define(token_stream)?,
             Some(&Token::PartialInclude(..)) => match token_stream.next() {
                 Some(Token::PartialInclude(partial_name, root)) => {
                     Ast::PartialInclude { partial_name, root }
@@ -97,11 +325,15 @@ where
 {
     let seq = sequence(&mut token_stream)?;
 
-    if let Some(x) = token_stream.next() {
-        return Err(Error::Mismatch {
-            expected: "EOF",
-            found: Some(x),
-        });
+    match token_stream.next() {
+        Some(Token::SectionCloser(closer)) => return Err(Error::StraySectionCloser { closer }),
+        Some(x) => {
+            return Err(Error::Mismatch {
+                expected: "EOF",
+                found: Some(Box::new(x)),
+            })
+        }
+        None => (),
     }
 
     Ok(seq)
@@ -133,7 +365,9 @@ mod test {
                 Ast::Literal("text a"),
                 Ast::Iteration {
                     name: simple_name("x"),
-                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),]))
+                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),])),
+                    separator: None,
+                    otherwise: None,
                 },
                 Ast::Literal("text c"),
             ]),
@@ -148,6 +382,166 @@ mod test {
         )
     }
 
+    #[test]
+    fn iteration_with_separator() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Iteration {
+                name: simple_name("x"),
+                nested: Box::new(Ast::Sequence(vec![Ast::Literal("item")])),
+                separator: Some(Box::new(Ast::Sequence(vec![Ast::Literal(", ")]))),
+                otherwise: None,
+            }]),
+            parse(vec![
+                Token::SectionOpener(SectionType::Iteration, simple_name("x")),
+                Token::Literal("item"),
+                Token::Separator("sep"),
+                Token::Literal(", "),
+                Token::SectionCloser(simple_name("x")),
+            ])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn separator_outside_iteration() {
+        let res = parse(vec![
+            Token::SectionOpener(SectionType::Conditional, simple_name("x")),
+            Token::Separator("sep"),
+            Token::SectionCloser(simple_name("x")),
+        ]);
+
+        match res {
+            Err(Error::Mismatch {
+                expected: "section closer",
+                found: Some(token),
+            }) if *token == Token::Separator("sep") => (),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn conditional_with_else() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Conditional {
+                name: simple_name("x"),
+                nested: Box::new(Ast::Sequence(vec![Ast::Literal("yes")])),
+                otherwise: Some(Box::new(Ast::Sequence(vec![Ast::Literal("no")]))),
+            }]),
+            parse(vec![
+                Token::SectionOpener(SectionType::Conditional, simple_name("x")),
+                Token::Literal("yes"),
+                Token::Else("else"),
+                Token::Literal("no"),
+                Token::SectionCloser(simple_name("x")),
+            ])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn else_after_separator() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Iteration {
+                name: simple_name("x"),
+                nested: Box::new(Ast::Sequence(vec![Ast::Literal("item")])),
+                separator: Some(Box::new(Ast::Sequence(vec![Ast::Literal(", ")]))),
+                otherwise: Some(Box::new(Ast::Sequence(vec![Ast::Literal("none")]))),
+            }]),
+            parse(vec![
+                Token::SectionOpener(SectionType::Iteration, simple_name("x")),
+                Token::Literal("item"),
+                Token::Separator("sep"),
+                Token::Literal(", "),
+                Token::Else("else"),
+                Token::Literal("none"),
+                Token::SectionCloser(simple_name("x")),
+            ])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn else_in_negative_iteration() {
+        let res = parse(vec![
+            Token::SectionOpener(SectionType::NegativeIteration, simple_name("x")),
+            Token::Else("else"),
+            Token::SectionCloser(simple_name("x")),
+        ]);
+
+        match res {
+            Err(Error::Mismatch {
+                expected: "section closer",
+                found: Some(token),
+            }) if *token == Token::Else("else") => (),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn if_section_with_branches() {
+        let condition = |name| Expression::Operand(Argument::Name(simple_name(name)));
+        assert_eq!(
+            Ast::Sequence(vec![Ast::If {
+                branches: vec![
+                    (condition("a"), Ast::Sequence(vec![Ast::Literal("a")])),
+                    (condition("b"), Ast::Sequence(vec![Ast::Literal("b")])),
+                ],
+                otherwise: Some(Box::new(Ast::Sequence(vec![Ast::Literal("c")]))),
+            }]),
+            parse(vec![
+                Token::If(simple_name("if"), Box::new(condition("a"))),
+                Token::Literal("a"),
+                Token::ElseIf("elif", Box::new(condition("b"))),
+                Token::Literal("b"),
+                Token::Else("else"),
+                Token::Literal("c"),
+                Token::SectionCloser(simple_name("if")),
+            ])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn match_section_with_arms() {
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Match {
+                name: simple_name("x"),
+                arms: vec![
+                    ("A", Ast::Sequence(vec![Ast::Literal("a")])),
+                    ("B", Ast::Sequence(vec![])),
+                ],
+                otherwise: None,
+            }]),
+            parse(vec![
+                Token::Match(simple_name("match"), Box::new(simple_name("x"))),
+                Token::Literal("\n  "),
+                Token::Arm("A"),
+                Token::Literal("a"),
+                Token::Arm("B"),
+                Token::SectionCloser(simple_name("match")),
+            ])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn text_before_first_match_arm() {
+        let res = parse(vec![
+            Token::Match(simple_name("match"), Box::new(simple_name("x"))),
+            Token::Literal("text"),
+            Token::Arm("A"),
+            Token::SectionCloser(simple_name("match")),
+        ]);
+
+        match res {
+            Err(Error::Mismatch {
+                expected: "match arm",
+                found: Some(token),
+            }) if *token == Token::Literal("text") => (),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
     #[test]
     fn simple_negative_iteration() {
         assert_eq!(
@@ -177,7 +571,8 @@ mod test {
                 Ast::Literal("text a"),
                 Ast::Conditional {
                     name: simple_name("x"),
-                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),]))
+                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),])),
+                    otherwise: None,
                 },
                 Ast::Literal("text c"),
             ]),
@@ -199,7 +594,8 @@ mod test {
                 Ast::Literal("text a"),
                 Ast::NegativeConditional {
                     name: simple_name("x"),
-                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),]))
+                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),])),
+                    otherwise: None,
                 },
                 Ast::Literal("text c"),
             ]),
@@ -221,7 +617,8 @@ mod test {
                 Ast::Literal("text a"),
                 Ast::Scope {
                     name: simple_name("x"),
-                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),]))
+                    nested: Box::new(Ast::Sequence(vec![Ast::Literal("text b"),])),
+                    otherwise: None,
                 },
                 Ast::Literal("text c"),
             ]),
@@ -243,7 +640,40 @@ mod test {
             Token::SectionCloser(simple_name("y")),
         ]);
 
-        assert!(res.is_err())
+        match res {
+            Err(Error::MismatchedSectionCloser { opener, closer }) => {
+                assert_eq!(simple_name("x"), *opener);
+                assert_eq!(simple_name("y"), *closer);
+            }
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn unclosed_section() {
+        let res = parse(vec![
+            Token::SectionOpener(SectionType::Iteration, simple_name("x")),
+            Token::SectionOpener(SectionType::Conditional, simple_name("y")),
+            Token::SectionCloser(simple_name("y")),
+        ]);
+
+        match res {
+            Err(Error::UnclosedSection { opener }) => assert_eq!(simple_name("x"), opener),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn stray_section_closer() {
+        let res = parse(vec![
+            Token::Literal("text"),
+            Token::SectionCloser(simple_name("x")),
+        ]);
+
+        match res {
+            Err(Error::StraySectionCloser { closer }) => assert_eq!(simple_name("x"), closer),
+            x => panic!("Unexpected result: {:?}", x),
+        }
     }
 
     #[test]
@@ -251,12 +681,12 @@ mod test {
         assert_eq!(
             Ast::Sequence(vec![
                 Ast::Literal("a"),
-                Ast::UnescapedInterpolation(simple_name("b")),
+                Ast::UnescapedInterpolation(simple_name("b"), None),
                 Ast::Literal("c"),
             ]),
             parse(vec![
                 Token::Literal("a"),
-                Token::UnescapedInterpolation(simple_name("b")),
+                Token::UnescapedInterpolation(simple_name("b"), None),
                 Token::Literal("c"),
             ])
             .unwrap()
@@ -268,9 +698,93 @@ mod test {
         assert_eq!(
             Ast::Sequence(vec![Ast::PartialInclude {
                 partial_name: "partial",
-                root: simple_name("a")
+                root: PartialRoot::Name(simple_name("a"))
             },]),
-            parse(vec![Token::PartialInclude("partial", simple_name("a"))]).unwrap()
+            parse(vec![Token::PartialInclude(
+                "partial",
+                PartialRoot::Name(simple_name("a"))
+            )])
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn parent_with_blocks() {
+        let parent = Name {
+            leading_dots: 0,
+            segments: vec![],
+            filters: vec![],
+            text: "layout.html",
+        };
+        let closer = Name {
+            text: "layout.html",
+            ..simple_name("layout")
+        };
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Parent {
+                partial_name: "layout.html",
+                blocks: vec![(
+                    "title",
+                    Ast::Sequence(vec![Ast::Block {
+                        name: "inner",
+                        nested: Box::new(Ast::Sequence(vec![Ast::Literal("Hello")])),
+                    }])
+                )],
+            }]),
+            parse(vec![
+                Token::Parent(parent),
+                Token::Literal("\n  "),
+                Token::Block(simple_name("title")),
+                Token::Block(simple_name("inner")),
+                Token::Literal("Hello"),
+                Token::SectionCloser(simple_name("inner")),
+                Token::SectionCloser(simple_name("title")),
+                Token::SectionCloser(closer),
+            ])
+            .unwrap()
         )
     }
+
+    #[test]
+    fn text_in_parent() {
+        let parent = Name {
+            leading_dots: 0,
+            segments: vec![],
+            filters: vec![],
+            text: "layout.html",
+        };
+        let res = parse(vec![
+            Token::Parent(parent),
+            Token::Literal("text"),
+            Token::SectionCloser(simple_name("layout.html")),
+        ]);
+
+        match res {
+            Err(Error::Mismatch {
+                expected: "block",
+                found: Some(token),
+            }) if *token == Token::Literal("text") => (),
+            x => panic!("Unexpected result: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn partial_definition() {
+        let res = parse(vec![
+            Token::Define(simple_name("define"), "row"),
+            Token::Interpolation(simple_name("a"), None),
+            Token::SectionCloser(simple_name("define")),
+        ]);
+
+        assert_eq!(
+            Ast::Sequence(vec![Ast::Define {
+                partial_name: "row",
+                nested: Box::new(Ast::Sequence(vec![Ast::Interpolation(
+                    simple_name("a"),
+                    None
+                )])),
+            }]),
+            res.unwrap()
+        );
+    }
 }
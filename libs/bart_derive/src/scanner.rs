@@ -1,18 +1,30 @@
 use crate::token::*;
+use std::iter::Peekable;
+use syn::ext::IdentExt;
+use syn::parse::Parser;
 
-const TAG_OPENER: &'static str = "{{";
-const TAG_CLOSER: &'static str = "}}";
-const UNESCAPED_TAG_CLOSER: &'static str = "}}}";
+pub const DEFAULT_DELIMITERS: Delimiters<'static> = Delimiters {
+    opener: "{{",
+    closer: "}}",
+};
 
 #[derive(Debug, PartialEq, Eq)]
-pub enum Error {
+pub enum ErrorKind {
     Mismatch,
+    UnclosedTag,
 }
 
-fn consume<'a>(input: &'a str, expected: &str) -> Result<&'a str, Error> {
+#[derive(Debug, PartialEq, Eq)]
+pub struct Error {
+    // Byte offset into the template of the tag where the error was found
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+fn consume<'a>(input: &'a str, expected: &str) -> Result<&'a str, ErrorKind> {
     match input.starts_with(expected) {
         true => Ok(&input[expected.len()..]),
-        false => Err(Error::Mismatch),
+        false => Err(ErrorKind::Mismatch),
     }
 }
 
@@ -20,19 +32,28 @@ fn not_dot(ch: char) -> bool {
     ch != '.'
 }
 
-pub fn segmented_name<'a>(input: &'a str) -> Result<Vec<&'a str>, Error> {
-    if input.len() > 0 {
-        input
-            .split('.')
-            .map(|segment| {
-                let ident = syn::parse_ident(segment);
-                let number = segment.parse::<u32>();
+// Keywords that cannot be raw identifiers, so no field or method has them as
+// its name
+const NON_RAW_KEYWORDS: &[&str] = &["self", "Self", "super", "crate", "_"];
 
-                if ident.is_err() && number.is_err() {
-                    return Err(Error::Mismatch);
-                }
+// The name of a field or method, which may be a keyword, to refer to fields
+// like `r#type`
+fn is_ident(segment: &str) -> bool {
+    syn::Ident::parse_any.parse_str(segment).is_ok() && !NON_RAW_KEYWORDS.contains(&segment)
+}
 
-                Ok(segment)
+// A field name, or the index of a tuple field
+fn is_member(segment: &str) -> bool {
+    is_ident(segment) || segment.parse::<u32>().is_ok()
+}
+
+pub fn segmented_name(input: &str) -> Result<Vec<&str>, ErrorKind> {
+    if !input.is_empty() {
+        input
+            .split('.')
+            .map(|segment| match is_member(segment) {
+                true => Ok(segment),
+                false => Err(ErrorKind::Mismatch),
             })
             .collect()
     } else {
@@ -40,54 +61,228 @@ pub fn segmented_name<'a>(input: &'a str) -> Result<Vec<&'a str>, Error> {
     }
 }
 
-pub fn name<'a>(input: &'a str) -> Result<(&'a str, Name<'a>), Error> {
+// A segment of a name, which is a field or a function call, as in `a` or
+// `b(1, c)` in {{a.b(1, c)}}
+fn segment<'a>(input: &'a str) -> Result<Segment<'a>, ErrorKind> {
+    let (name, call) = match input.strip_suffix(')') {
+        Some(call) => {
+            let paren = call.find('(').ok_or(ErrorKind::Mismatch)?;
+            let arguments = arguments(&call[paren + 1..])?;
+            (call[..paren].trim_end(), Some(arguments))
+        }
+        None => (input, None),
+    };
+
+    // Calls must be to methods or fields holding functions, not to tuple
+    // fields by index
+    let valid = match call {
+        Some(_) => is_ident(name),
+        None => is_member(name),
+    };
+    match valid {
+        true => Ok(Segment { name, call }),
+        false => Err(ErrorKind::Mismatch),
+    }
+}
+
+pub fn name<'a>(input: &'a str) -> Result<(&'a str, Name<'a>), ErrorKind> {
     let input = input.trim();
+    let text = input;
 
     let leading_dots = input.find(not_dot).unwrap_or(input.len());
     let input = input[leading_dots..].trim_start();
 
-    let (function_call, input) = match input.ends_with("()") {
-        true => (true, input[..input.len() - 2].trim_end()),
-        false => (false, input),
-    };
-
-    if leading_dots == 0 && input.len() == 0 {
-        return Err(Error::Mismatch);
+    if leading_dots == 0 && input.is_empty() {
+        return Err(ErrorKind::Mismatch);
     }
 
-    let segments = segmented_name(input)?;
+    // Loop variables, like @index, are single identifiers
+    let segments = if let Some(variable) = input.strip_prefix('@') {
+        syn::parse_str::<syn::Ident>(variable).map_err(|_| ErrorKind::Mismatch)?;
+        vec![Segment {
+            name: input,
+            call: None,
+        }]
+    } else if input.starts_with('(') && leading_dots > 0 {
+        // Calling the value itself, as in {{.()}}
+        let inner = input[1..].strip_suffix(')').ok_or(ErrorKind::Mismatch)?;
+        vec![Segment {
+            name: &input[0..0],
+            call: Some(arguments(inner)?),
+        }]
+    } else if input.is_empty() {
+        vec![]
+    } else {
+        split_top_level(input, '.')
+            .into_iter()
+            .map(segment)
+            .collect::<Result<_, _>>()?
+    };
 
     Ok((
         &input[0..0],
         Name {
             leading_dots: num::cast::cast(leading_dots).unwrap(),
-            segments: segments,
-            function_call,
+            segments,
+            filters: vec![],
+            text,
         },
     ))
 }
 
-fn at_end(input: &str) -> Result<(), Error> {
+fn at_end(input: &str) -> Result<(), ErrorKind> {
     match input.len() {
         0 => Ok(()),
-        _ => Err(Error::Mismatch),
+        _ => Err(ErrorKind::Mismatch),
+    }
+}
+
+// A format spec as for `format!`, such as `>8.2` or `x?`. Width and
+// precision must be given literally, not as arguments.
+fn format_spec(input: &str) -> Result<&str, ErrorKind> {
+    let spec = input.trim_end();
+    let is_align = |ch: char| matches!(ch, '<' | '^' | '>');
+
+    let mut rest = spec;
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(fill), Some(align)) if is_align(align) && fill != '{' && fill != '}' => {
+            rest = chars.as_str()
+        }
+        (Some(align), _) if is_align(align) => rest = &rest[1..],
+        _ => (),
+    }
+
+    rest = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+    rest = rest.strip_prefix('#').unwrap_or(rest);
+    rest = rest.strip_prefix('0').unwrap_or(rest);
+    rest = rest.trim_start_matches(|ch: char| ch.is_ascii_digit());
+
+    if let Some(precision) = rest.strip_prefix('.') {
+        rest = precision.trim_start_matches(|ch: char| ch.is_ascii_digit());
+        if rest.len() == precision.len() {
+            return Err(ErrorKind::Mismatch);
+        }
+    }
+
+    match rest {
+        "" | "?" | "x?" | "X?" | "x" | "X" | "o" | "b" | "e" | "E" | "p" => Ok(spec),
+        _ => Err(ErrorKind::Mismatch),
     }
 }
 
-fn interpolation<'a>(input: &'a str) -> Result<Token<'a>, Error> {
+// The position of the first `separator` which is not within parentheses or
+// a quoted literal. With `)` as the separator, this finds the parenthesis
+// closing one that was opened before the input.
+fn find_top_level(input: &str, separator: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (pos, ch) in input.char_indices() {
+        if let Some(quote_char) = quote {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == quote_char {
+                quote = None;
+            }
+            continue;
+        }
+
+        match ch {
+            _ if ch == separator && depth == 0 => return Some(pos),
+            '"' | '\'' => quote = Some(ch),
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+fn split_top_level(mut input: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    while let Some(pos) = find_top_level(input, separator) {
+        parts.push(&input[..pos]);
+        input = &input[pos + separator.len_utf8()..];
+    }
+    parts.push(input);
+    parts
+}
+
+// The comma separated arguments within the parentheses of a call
+fn arguments<'a>(input: &'a str) -> Result<Vec<Argument<'a>>, ErrorKind> {
+    match input.trim() {
+        "" => Ok(vec![]),
+        _ => split_top_level(input, ',')
+            .into_iter()
+            .map(argument)
+            .collect(),
+    }
+}
+
+fn argument<'a>(input: &'a str) -> Result<Argument<'a>, ErrorKind> {
+    let input = input.trim();
+
+    let number = input.strip_prefix('-').unwrap_or(input);
+    if syn::parse_str::<syn::Lit>(number).is_ok() {
+        return Ok(Argument::Literal(input));
+    }
+
     let (rest, name) = name(input)?;
     at_end(rest)?;
-    Ok(Token::Interpolation(name))
+    Ok(Argument::Name(name))
 }
 
-fn unescaped_interpolation<'a>(input: &'a str) -> Result<Token<'a>, Error> {
-    let input = consume(input, "{")?;
-    let (rest, name) = name(input)?;
+fn filter<'a>(input: &'a str) -> Result<Filter<'a>, ErrorKind> {
+    let input = input.trim();
+
+    let (name, arguments) = match input.find('(') {
+        Some(paren) => {
+            let inner = input[paren + 1..]
+                .strip_suffix(')')
+                .ok_or(ErrorKind::Mismatch)?;
+            (input[..paren].trim_end(), arguments(inner)?)
+        }
+        None => (input, vec![]),
+    };
+
+    syn::parse_str::<syn::Ident>(name).map_err(|_| ErrorKind::Mismatch)?;
+
+    Ok(Filter { name, arguments })
+}
+
+// The name of an interpolation, with filters and a format spec, as in
+// {{name | truncate(20) : >20}}
+fn formatted_name<'a>(input: &'a str) -> Result<(Name<'a>, Option<&'a str>), ErrorKind> {
+    let (input, format_spec) = match find_top_level(input, ':') {
+        Some(colon) => (&input[..colon], Some(format_spec(&input[colon + 1..])?)),
+        None => (input, None),
+    };
+
+    let mut stages = split_top_level(input, '|').into_iter();
+    let (rest, mut name) = name(stages.next().unwrap())?;
     at_end(rest)?;
-    Ok(Token::UnescapedInterpolation(name))
+    name.filters = stages.map(filter).collect::<Result<_, _>>()?;
+
+    Ok((name, format_spec))
 }
 
-fn section_opener<'a>(input: &'a str) -> Result<Token<'a>, Error> {
+fn interpolation<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
+    let (name, format_spec) = formatted_name(input)?;
+    Ok(Token::Interpolation(name, format_spec))
+}
+
+fn unescaped_interpolation<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
+    let input = consume(input, "{")?;
+    let (name, format_spec) = formatted_name(input)?;
+    Ok(Token::UnescapedInterpolation(name, format_spec))
+}
+
+fn section_opener<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
     enum Head {
         Positive,
         Negative,
@@ -103,14 +298,14 @@ fn section_opener<'a>(input: &'a str) -> Result<Token<'a>, Error> {
     let head = match input.chars().next() {
         Some('#') => Ok(Head::Positive),
         Some('^') => Ok(Head::Negative),
-        _ => Err(Error::Mismatch),
+        _ => Err(ErrorKind::Mismatch),
     }?;
     let input = &input[1..];
 
-    let (input, tail) = if input.ends_with('?') {
-        (&input[..input.len() - 1], Tail::Conditional)
-    } else if input.ends_with('.') {
-        (&input[..input.len() - 1], Tail::Scope)
+    let (input, tail) = if let Some(input) = input.strip_suffix('?') {
+        (input, Tail::Conditional)
+    } else if let Some(input) = input.strip_suffix('.') {
+        (input, Tail::Scope)
     } else {
         (input, Tail::None)
     };
@@ -125,65 +320,387 @@ fn section_opener<'a>(input: &'a str) -> Result<Token<'a>, Error> {
         (Head::Positive, Tail::Conditional) => Ok(SectionType::Conditional),
         (Head::Negative, Tail::Conditional) => Ok(SectionType::NegativeConditional),
         (Head::Positive, Tail::Scope) => Ok(SectionType::Scope),
-        _ => Err(Error::Mismatch),
+        _ => Err(ErrorKind::Mismatch),
     }?;
 
     Ok(Token::SectionOpener(section_type, name))
 }
 
-fn section_closer<'a>(input: &'a str) -> Result<Token<'a>, Error> {
+// The name of a template file, as in {{<layouts/base.html}}
+fn file_name(input: &str) -> Result<Name<'_>, ErrorKind> {
+    let input = input.trim();
+    match input.is_empty() || input.contains(char::is_whitespace) {
+        true => Err(ErrorKind::Mismatch),
+        false => Ok(Name {
+            leading_dots: 0,
+            segments: vec![],
+            filters: vec![],
+            text: input,
+        }),
+    }
+}
+
+fn section_closer<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
     let input = consume(input, "/")?;
+
+    // Parent templates are closed by their file name, as in
+    // {{/layouts/base.html}}, which need not be a valid name
+    let name = match name(input) {
+        Ok((rest, name)) => {
+            at_end(rest)?;
+            name
+        }
+        Err(_) => file_name(input)?,
+    };
+    Ok(Token::SectionCloser(name))
+}
+
+fn parent_opener<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
+    let input = consume(input, "<")?;
+    Ok(Token::Parent(file_name(input)?))
+}
+
+fn block_opener<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
+    let input = consume(input, "$")?;
     let (rest, name) = name(input)?;
     at_end(rest)?;
-    Ok(Token::SectionCloser(name))
+
+    // Blocks are named by a single identifier
+    match (name.leading_dots, &name.segments[..]) {
+        (0, [segment]) if segment.call.is_none() && !segment.name.starts_with('@') => {
+            Ok(Token::Block(name))
+        }
+        _ => Err(ErrorKind::Mismatch),
+    }
+}
+
+fn define_opener(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let (keyword, partial_name) = keyword_tag(input, "*", "define").ok_or(ErrorKind::Mismatch)?;
+    Ok(Token::Define(
+        name(keyword)?.1,
+        file_name(partial_name)?.text,
+    ))
 }
 
-fn partial_include<'a>(input: &'a str) -> Result<Token<'a>, Error> {
+// The named arguments of a partial include, as in label="Save" kind=.primary
+fn named_arguments<'a>(input: &'a str) -> Result<Vec<(&'a str, Argument<'a>)>, ErrorKind> {
+    split_top_level(input, ' ')
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (parameter, value) = part.split_once('=').ok_or(ErrorKind::Mismatch)?;
+            syn::parse_str::<syn::Ident>(parameter).map_err(|_| ErrorKind::Mismatch)?;
+            Ok((parameter, argument(value)?))
+        })
+        .collect()
+}
+
+fn partial_include<'a>(input: &'a str) -> Result<Token<'a>, ErrorKind> {
     let inner = consume(input, ">")?
         .trim()
         .splitn(2, ' ')
         .collect::<Vec<_>>();
     let partial_name = inner[0];
-    let segments = match inner.get(1) {
-        Some(root) => name(root)?.1,
-        None => Name {
+    let root = match inner.get(1) {
+        Some(arguments) if find_top_level(arguments, '=').is_some() => {
+            PartialRoot::Arguments(named_arguments(arguments)?)
+        }
+        Some(root) => PartialRoot::Name(name(root)?.1),
+        None => PartialRoot::Name(Name {
             leading_dots: 1,
             segments: vec![],
-            function_call: false,
-        },
+            filters: vec![],
+            text: &partial_name[partial_name.len()..],
+        }),
     };
 
-    Ok(Token::PartialInclude(partial_name, segments))
+    Ok(Token::PartialInclude(partial_name, root))
+}
+
+pub fn delimiters(input: &str) -> Result<Delimiters<'_>, ErrorKind> {
+    let mut parts = input.split_whitespace();
+    let opener = parts.next().ok_or(ErrorKind::Mismatch)?;
+    let closer = parts.next().ok_or(ErrorKind::Mismatch)?;
+
+    if parts.next().is_some() || opener.contains('=') || closer.contains('=') {
+        return Err(ErrorKind::Mismatch);
+    }
+
+    Ok(Delimiters { opener, closer })
+}
+
+fn set_delimiters(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let input = consume(input, "=")?;
+    let input = input.strip_suffix('=').ok_or(ErrorKind::Mismatch)?;
+    Ok(Token::SetDelimiters(delimiters(input)?))
+}
+
+// {{:sep}} or a match arm, such as {{:Active}} or {{:Status::Active}}
+fn separator_or_arm(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let input = consume(input, ":")?.trim();
+    match input {
+        "sep" => Ok(Token::Separator(input)),
+        _ => match syn::parse_str::<syn::Path>(input) {
+            Ok(_) => Ok(Token::Arm(input)),
+            Err(_) => Err(ErrorKind::Mismatch),
+        },
+    }
+}
+
+fn else_clause(input: &str) -> Result<Token<'_>, ErrorKind> {
+    match input.trim() {
+        "else" => Ok(Token::Else(input.trim())),
+        _ => Err(ErrorKind::Mismatch),
+    }
+}
+
+const OPERATORS: &[&str] = &["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")"];
+
+const COMPARISONS: &[&str] = &["==", "!=", "<=", ">=", "<", ">"];
+
+// The length of the operand at the start of the input, which is a literal or
+// a name, such as `"active"`, `-1` or `.items.len()`
+fn operand_len(input: &str) -> Result<usize, ErrorKind> {
+    let mut chars = input.char_indices();
+    if let Some((_, quote @ ('"' | '\''))) = chars.next() {
+        let mut escaped = false;
+        for (pos, ch) in chars {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if ch == quote => return Ok(pos + 1),
+                _ => (),
+            }
+        }
+        return Err(ErrorKind::Mismatch);
+    }
+
+    // A name, where calls may be followed by more segments, as in `a(1).b`
+    let mut start = 0;
+    loop {
+        let rest = &input[start..];
+        let pos = match rest.find(|ch: char| ch.is_whitespace() || "&|!=<>()".contains(ch)) {
+            Some(pos) => pos,
+            None => return Ok(input.len()),
+        };
+
+        if start + pos == 0 {
+            return Err(ErrorKind::Mismatch);
+        }
+        if !rest[pos..].starts_with('(') {
+            return Ok(start + pos);
+        }
+
+        let arguments = &rest[pos + 1..];
+        let end = find_top_level(arguments, ')').ok_or(ErrorKind::Mismatch)?;
+        start += pos + 1 + end + 1;
+        if !input[start..].starts_with('.') {
+            return Ok(start);
+        }
+    }
+}
+
+// Split an expression into operators and operands
+fn expression_tokens(mut input: &str) -> Result<Vec<&str>, ErrorKind> {
+    let mut tokens = vec![];
+
+    loop {
+        input = input.trim_start();
+        if input.is_empty() {
+            return Ok(tokens);
+        }
+
+        let len = match OPERATORS.iter().find(|&&op| input.starts_with(op)) {
+            Some(op) => op.len(),
+            None => operand_len(input)?,
+        };
+        tokens.push(&input[..len]);
+        input = &input[len..];
+    }
+}
+
+fn operand<'a>(token: Option<&'a str>) -> Result<Argument<'a>, ErrorKind> {
+    match token {
+        Some(token) if !OPERATORS.contains(&token) => argument(token),
+        _ => Err(ErrorKind::Mismatch),
+    }
+}
+
+// An operand, a parenthesized expression or `!` applied to either, which
+// binds tighter than comparisons, as in Rust
+fn unary_expression<'a, T>(tokens: &mut Peekable<T>) -> Result<Expression<'a>, ErrorKind>
+where
+    T: Iterator<Item = &'a str>,
+{
+    if tokens.next_if_eq(&"!").is_some() {
+        return Ok(Expression::Not(Box::new(unary_expression(tokens)?)));
+    }
+
+    if tokens.next_if_eq(&"(").is_some() {
+        let expression = or_expression(tokens)?;
+        tokens.next_if_eq(&")").ok_or(ErrorKind::Mismatch)?;
+        return Ok(expression);
+    }
+
+    Ok(Expression::Operand(operand(tokens.next())?))
+}
+
+fn primary_expression<'a, T>(tokens: &mut Peekable<T>) -> Result<Expression<'a>, ErrorKind>
+where
+    T: Iterator<Item = &'a str>,
+{
+    let lhs = unary_expression(tokens)?;
+    match tokens.next_if(|token| COMPARISONS.contains(token)) {
+        Some(operator) => Ok(Expression::Comparison(
+            Box::new(lhs),
+            operator,
+            Box::new(unary_expression(tokens)?),
+        )),
+        None => Ok(lhs),
+    }
+}
+
+fn and_expression<'a, T>(tokens: &mut Peekable<T>) -> Result<Expression<'a>, ErrorKind>
+where
+    T: Iterator<Item = &'a str>,
+{
+    let mut expression = primary_expression(tokens)?;
+    while tokens.next_if_eq(&"&&").is_some() {
+        let rhs = primary_expression(tokens)?;
+        expression = Expression::And(Box::new(expression), Box::new(rhs));
+    }
+    Ok(expression)
+}
+
+fn or_expression<'a, T>(tokens: &mut Peekable<T>) -> Result<Expression<'a>, ErrorKind>
+where
+    T: Iterator<Item = &'a str>,
+{
+    let mut expression = and_expression(tokens)?;
+    while tokens.next_if_eq(&"||").is_some() {
+        let rhs = and_expression(tokens)?;
+        expression = Expression::Or(Box::new(expression), Box::new(rhs));
+    }
+    Ok(expression)
+}
+
+// A boolean expression with `&&`, `||`, `!`, parentheses and comparisons of
+// literals and names
+fn expression(input: &str) -> Result<Expression<'_>, ErrorKind> {
+    let mut tokens = expression_tokens(input)?.into_iter().peekable();
+    let expression = or_expression(&mut tokens)?;
+    match tokens.next() {
+        Some(_) => Err(ErrorKind::Mismatch),
+        None => Ok(expression),
+    }
+}
+
+// Split a tag like `#if x > 0` into the keyword and the rest, if the tag
+// consists of the prefix, the keyword and whitespace followed by more
+fn keyword_tag<'a>(input: &'a str, prefix: &str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let input = input.trim_start().strip_prefix(prefix)?;
+    let rest = input.strip_prefix(keyword)?;
+    match rest.starts_with(char::is_whitespace) {
+        true => Some((&input[..keyword.len()], rest)),
+        false => None,
+    }
+}
+
+fn if_opener(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let (keyword, condition) = keyword_tag(input, "#", "if").ok_or(ErrorKind::Mismatch)?;
+    Ok(Token::If(
+        name(keyword)?.1,
+        Box::new(expression(condition)?),
+    ))
 }
 
-fn bart_tag<'a>(input: &'a str) -> Result<(&'a str, Token<'a>), Error> {
-    let input = consume(input, TAG_OPENER)?;
+fn match_opener(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let (keyword, scrutinee) = keyword_tag(input, "#", "match").ok_or(ErrorKind::Mismatch)?;
+    let (rest, scrutinee) = name(scrutinee)?;
+    at_end(rest)?;
+    Ok(Token::Match(name(keyword)?.1, Box::new(scrutinee)))
+}
+
+fn else_if(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let (keyword, condition) = keyword_tag(input, "", "elif").ok_or(ErrorKind::Mismatch)?;
+    Ok(Token::ElseIf(keyword, Box::new(expression(condition)?)))
+}
+
+fn comment(input: &str) -> Result<Token<'_>, ErrorKind> {
+    let input = consume(input, "!")?;
+    Ok(Token::Comment(input))
+}
+
+// Find the end of the tag meat and the start of the rest of the input. A
+// whitespace control marker, `~`, before the closer is not part of the meat.
+// Unescaped interpolations must additionally end with `}`.
+fn tag_end(input: &str, closer: &str, unescaped: bool) -> Option<(usize, usize)> {
+    let mut search_from = 0;
+
+    while let Some(found) = input[search_from..].find(closer) {
+        let closer_start = search_from + found;
+        let meat = &input[..closer_start];
+        let meat = meat.strip_suffix('~').unwrap_or(meat);
+
+        match unescaped {
+            false => return Some((meat.len(), closer_start + closer.len())),
+            true => {
+                if let Some(meat) = meat.strip_suffix('}') {
+                    return Some((meat.len(), closer_start + closer.len()));
+                }
+            }
+        }
+
+        search_from = closer_start + closer.chars().next()?.len_utf8();
+    }
+
+    None
+}
+
+fn bart_tag<'a>(
+    input: &'a str,
+    delimiters: Delimiters<'_>,
+) -> Result<(&'a str, Token<'a>), ErrorKind> {
+    let input = consume(input, delimiters.opener)?;
+    let input = input.strip_prefix('~').unwrap_or(input);
 
     let peek = input.chars().next();
-    let tag_closer = match peek {
-        Some('{') => UNESCAPED_TAG_CLOSER,
-        _ => TAG_CLOSER,
-    };
 
-    let end = input.find(tag_closer).ok_or(Error::Mismatch)?;
+    let (end, rest_start) =
+        tag_end(input, delimiters.closer, peek == Some('{')).ok_or(ErrorKind::UnclosedTag)?;
     let tag_meat = &input[..end];
-    let rest = &input[end + tag_closer.len()..];
+    let rest = &input[rest_start..];
 
     let tag = match peek {
+        Some('#') if keyword_tag(tag_meat, "#", "if").is_some() => if_opener(tag_meat)?,
+        Some('#') if keyword_tag(tag_meat, "#", "match").is_some() => match_opener(tag_meat)?,
         Some('#') => section_opener(tag_meat)?,
         Some('^') => section_opener(tag_meat)?,
         Some('/') => section_closer(tag_meat)?,
         Some('>') => partial_include(tag_meat)?,
+        Some('<') => parent_opener(tag_meat)?,
+        Some('$') => block_opener(tag_meat)?,
+        Some('*') => define_opener(tag_meat)?,
+        Some('!') => comment(tag_meat)?,
+        Some('=') => set_delimiters(tag_meat)?,
+        Some(':') => separator_or_arm(tag_meat)?,
         Some('{') => unescaped_interpolation(tag_meat)?,
+        // `else` is a keyword, so it cannot be a plain field name anyway.
+        // Fields named `r#else` are still available as `{{r#else}}`.
+        Some(_) if tag_meat.trim() == "else" => else_clause(tag_meat)?,
+        Some(_) if keyword_tag(tag_meat, "", "elif").is_some() => else_if(tag_meat)?,
         Some(_) => interpolation(tag_meat)?,
-        None => return Err(Error::Mismatch),
+        None => return Err(ErrorKind::Mismatch),
     };
 
     Ok((rest, tag))
 }
 
-fn literal_text<'a>(input: &'a str) -> Result<(&'a str, Option<Token<'a>>), Error> {
-    match input.find(TAG_OPENER) {
+fn literal_text<'a>(
+    input: &'a str,
+    delimiters: Delimiters<'_>,
+) -> Result<(&'a str, Option<Token<'a>>), ErrorKind> {
+    match input.find(delimiters.opener) {
         Some(0) => Ok((input, None)),
         Some(index) => Ok((&input[index..], Some(Token::Literal(&input[0..index])))),
         None => Ok((
@@ -196,11 +713,20 @@ fn literal_text<'a>(input: &'a str) -> Result<(&'a str, Option<Token<'a>>), Erro
     }
 }
 
-pub fn sequence<'a>(mut input: &'a str) -> Result<Vec<Token<'a>>, Error> {
+pub fn sequence<'a>(
+    mut input: &'a str,
+    mut delimiters: Delimiters<'a>,
+) -> Result<Vec<Token<'a>>, Error> {
+    let template = input;
+    let error_at = |rest: &str| {
+        let offset = template.len() - rest.len();
+        move |kind| Error { offset, kind }
+    };
+
     let mut seq = vec![];
 
     loop {
-        let (rest, literal_opt) = literal_text(input)?;
+        let (rest, literal_opt) = literal_text(input, delimiters).map_err(error_at(input))?;
 
         if let Some(literal) = literal_opt {
             seq.push(literal);
@@ -210,10 +736,25 @@ pub fn sequence<'a>(mut input: &'a str) -> Result<Vec<Token<'a>>, Error> {
             break;
         }
 
-        let (rest, tag) = bart_tag(rest)?;
+        let (after_tag, tag) = bart_tag(rest, delimiters).map_err(error_at(rest))?;
+
+        let tag_text = &rest[..rest.len() - after_tag.len()];
+        let trim_before = tag_text[delimiters.opener.len()..].starts_with('~');
+        let trim_after = tag_text[..tag_text.len() - delimiters.closer.len()].ends_with('~');
+
+        if let Token::SetDelimiters(new_delimiters) = tag {
+            delimiters = new_delimiters;
+        }
+
+        if trim_before {
+            seq.push(Token::TrimWhitespace);
+        }
         seq.push(tag);
+        if trim_after {
+            seq.push(Token::TrimWhitespace);
+        }
 
-        input = rest;
+        input = after_tag;
     }
 
     Ok(seq)
@@ -231,33 +772,148 @@ mod tests {
 
     #[test]
     fn consume_mismatches() {
-        assert_eq!(Err(Error::Mismatch), consume("{{ape}}", "{a"));
+        assert_eq!(Err(ErrorKind::Mismatch), consume("{{ape}}", "{a"));
     }
 
     #[test]
     fn bart_tag_matches() {
         assert_eq!(
-            Ok(("tail", Token::Interpolation(simple_name("ape")))),
-            bart_tag("{{ape}}tail")
+            Ok(("tail", Token::Interpolation(simple_name("ape"), None))),
+            bart_tag("{{ape}}tail", DEFAULT_DELIMITERS)
         );
     }
 
     #[test]
     fn bart_tag_matches_name_with_underscore() {
         assert_eq!(
-            Ok(("tail", Token::Interpolation(simple_name("ape_katt")))),
-            bart_tag("{{ape_katt}}tail")
+            Ok(("tail", Token::Interpolation(simple_name("ape_katt"), None))),
+            bart_tag("{{ape_katt}}tail", DEFAULT_DELIMITERS)
         );
     }
 
+    #[test]
+    fn bart_tag_matches_loop_variables() {
+        let name = Name {
+            leading_dots: 2,
+            segments: fields(&["@index"]),
+            filters: vec![],
+            text: "..@index",
+        };
+        assert_eq!(
+            Ok(("tail", Token::Interpolation(name, None))),
+            bart_tag("{{..@index}}tail", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_mismatches_malformed_loop_variables() {
+        assert!(bart_tag("{{@index()}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{@index.a}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{a.@index}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{@}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_format_spec() {
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::Interpolation(simple_name("price"), Some(".2"))
+            )),
+            bart_tag("{{price:.2}}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::UnescapedInterpolation(simple_name("id"), Some("08"))
+            )),
+            bart_tag("{{{id:08}}}tail", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_matches_filters() {
+        let mut name = simple_name("name");
+        name.filters = vec![
+            Filter {
+                name: "upper",
+                arguments: vec![],
+            },
+            Filter {
+                name: "truncate",
+                arguments: vec![Argument::Literal("20")],
+            },
+            Filter {
+                name: "default",
+                arguments: vec![
+                    Argument::Literal("\"a, (b) | c: d\""),
+                    Argument::Name(simple_name("other")),
+                    Argument::Literal("-1"),
+                ],
+            },
+        ];
+
+        assert_eq!(
+            Ok(("tail", Token::Interpolation(name, Some(">5")))),
+            bart_tag(
+                "{{ name | upper | truncate(20) | default(\"a, (b) | c: d\", other, -1):>5}}tail",
+                DEFAULT_DELIMITERS
+            )
+        );
+    }
+
+    #[test]
+    fn bart_tag_mismatches_invalid_filters() {
+        for tag in &[
+            "{{a | }}",
+            "{{a | b c}}",
+            "{{a | b(}}",
+            "{{a | b(c d)}}",
+            "{{a | 1}}",
+        ] {
+            assert_eq!(Err(ErrorKind::Mismatch), bart_tag(tag, DEFAULT_DELIMITERS));
+        }
+    }
+
+    #[test]
+    fn format_spec_matches() {
+        for spec in &[
+            "",
+            "e",
+            "?",
+            "#x?",
+            "*^+#010.3e",
+            " >5",
+            "<",
+            "0",
+            ".0",
+            "p",
+        ] {
+            assert_eq!(Ok(*spec), format_spec(spec));
+        }
+    }
+
+    #[test]
+    fn format_spec_mismatches() {
+        for spec in &["q", ".", "5$", ".*", "{<5", "x.2", "width$"] {
+            assert_eq!(Err(ErrorKind::Mismatch), format_spec(spec));
+        }
+    }
+
     #[test]
     fn bart_tag_mismatches() {
-        assert_eq!(Err(Error::Mismatch), bart_tag("head{{ape}}"));
+        assert_eq!(
+            Err(ErrorKind::Mismatch),
+            bart_tag("head{{ape}}", DEFAULT_DELIMITERS)
+        );
     }
 
     #[test]
     fn bart_tag_must_be_closed() {
-        assert_eq!(Err(Error::Mismatch), bart_tag("{{ape"));
+        assert_eq!(
+            Err(ErrorKind::UnclosedTag),
+            bart_tag("{{ape", DEFAULT_DELIMITERS)
+        );
     }
 
     #[test]
@@ -267,7 +923,7 @@ mod tests {
                 "",
                 Token::SectionOpener(SectionType::Iteration, simple_name("ape"))
             )),
-            bart_tag("{{#ape}}")
+            bart_tag("{{#ape}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -278,7 +934,7 @@ mod tests {
                 "",
                 Token::SectionOpener(SectionType::NegativeIteration, simple_name("ape"))
             )),
-            bart_tag("{{^ape}}")
+            bart_tag("{{^ape}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -289,7 +945,7 @@ mod tests {
                 "",
                 Token::SectionOpener(SectionType::Conditional, simple_name("ape"))
             )),
-            bart_tag("{{#ape?}}")
+            bart_tag("{{#ape?}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -300,7 +956,7 @@ mod tests {
                 "",
                 Token::SectionOpener(SectionType::NegativeConditional, simple_name("ape"))
             )),
-            bart_tag("{{^ape?}}")
+            bart_tag("{{^ape?}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -311,7 +967,7 @@ mod tests {
                 "",
                 Token::SectionOpener(SectionType::Scope, simple_name("ape"))
             )),
-            bart_tag("{{#ape.}}")
+            bart_tag("{{#ape.}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -319,7 +975,7 @@ mod tests {
     fn bart_tag_matches_section_closer() {
         assert_eq!(
             Ok(("", Token::SectionCloser(simple_name("ape")))),
-            bart_tag("{{/ape}}")
+            bart_tag("{{/ape}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -330,34 +986,378 @@ mod tests {
                 "",
                 Token::PartialInclude(
                     "ape",
-                    Name {
+                    PartialRoot::Name(Name {
                         leading_dots: 1,
                         segments: vec![],
-                        function_call: false
-                    }
+                        filters: vec![],
+                        text: ""
+                    })
+                )
+            )),
+            bart_tag("{{>ape}}", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_matches_partial_include_with_named_arguments() {
+        assert_eq!(
+            Ok((
+                "",
+                Token::PartialInclude(
+                    "button",
+                    PartialRoot::Arguments(vec![
+                        ("label", Argument::Literal("\"Save changes\"")),
+                        ("size", Argument::Literal("2")),
+                        (
+                            "kind",
+                            Argument::Name(Name {
+                                leading_dots: 1,
+                                segments: fields(&["primary"]),
+                                filters: vec![],
+                                text: ".primary"
+                            })
+                        ),
+                    ])
+                )
+            )),
+            bart_tag(
+                "{{>button label=\"Save changes\"  size=2 kind=.primary}}",
+                DEFAULT_DELIMITERS
+            )
+        );
+        assert!(bart_tag("{{>button label=}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{>button label=\"a\" b}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{>button 1=\"a\"}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_parent_tags() {
+        let parent = Name {
+            leading_dots: 0,
+            segments: vec![],
+            filters: vec![],
+            text: "layouts/base.html",
+        };
+        assert_eq!(
+            Ok(("tail", Token::Parent(parent))),
+            bart_tag("{{< layouts/base.html }}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("tail", Token::Block(simple_name("content")))),
+            bart_tag("{{$ content }}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("tail", Token::SectionCloser(simple_name("content")))),
+            bart_tag("{{/content}}tail", DEFAULT_DELIMITERS)
+        );
+        assert!(bart_tag("{{<}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{<a b}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{$a.b}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{$.a}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_partial_definitions() {
+        assert_eq!(
+            Ok(("tail", Token::Define(simple_name("define"), "row"))),
+            bart_tag("{{*define row}}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("tail", Token::SectionCloser(simple_name("define")))),
+            bart_tag("{{/define}}tail", DEFAULT_DELIMITERS)
+        );
+        assert!(bart_tag("{{*define}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{*define a b}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{*row}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_file_names_in_section_closers() {
+        let closer = Name {
+            leading_dots: 0,
+            segments: vec![],
+            filters: vec![],
+            text: "/layouts/base.html",
+        };
+        assert_eq!(
+            Ok(("", Token::SectionCloser(closer))),
+            bart_tag("{{/ /layouts/base.html }}", DEFAULT_DELIMITERS)
+        );
+        assert!(bart_tag("{{/a b}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_separator() {
+        assert_eq!(
+            Ok(("tail", Token::Separator("sep"))),
+            bart_tag("{{: sep }}tail", DEFAULT_DELIMITERS)
+        );
+        assert!(bart_tag("{{:other sep}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_match_tags() {
+        let keyword = Name {
+            text: "match",
+            ..simple_name("match")
+        };
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::Match(keyword, Box::new(simple_name("status")))
+            )),
+            bart_tag("{{#match status}}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("tail", Token::Arm("Status::Active"))),
+            bart_tag("{{: Status::Active }}tail", DEFAULT_DELIMITERS)
+        );
+        assert!(bart_tag("{{#match a b}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_else() {
+        assert_eq!(
+            Ok(("tail", Token::Else("else"))),
+            bart_tag("{{ else }}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("tail", Token::Interpolation(simple_name("r#else"), None))),
+            bart_tag("{{r#else}}tail", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn expression_respects_precedence() {
+        use Expression::*;
+
+        assert_eq!(
+            Ok(Or(
+                Box::new(Operand(Argument::Name(simple_name("a")))),
+                Box::new(And(
+                    Box::new(Not(Box::new(Operand(Argument::Name(simple_name("b")))))),
+                    Box::new(Comparison(
+                        Box::new(Operand(Argument::Name(simple_name("c")))),
+                        ">=",
+                        Box::new(Operand(Argument::Literal("-1")))
+                    ))
+                ))
+            )),
+            expression("a || !b && c >= -1")
+        );
+    }
+
+    #[test]
+    fn expression_applies_not_before_comparison() {
+        use Expression::*;
+
+        assert_eq!(
+            Ok(Comparison(
+                Box::new(Not(Box::new(Operand(Argument::Name(simple_name("a")))))),
+                "==",
+                Box::new(Operand(Argument::Name(simple_name("b"))))
+            )),
+            expression("!a == b")
+        );
+        assert_eq!(
+            Ok(Not(Box::new(Comparison(
+                Box::new(Operand(Argument::Name(simple_name("a")))),
+                "==",
+                Box::new(Operand(Argument::Name(simple_name("b"))))
+            )))),
+            expression("!(a == b)")
+        );
+    }
+
+    #[test]
+    fn expression_matches_parentheses_and_literals() {
+        use Expression::*;
+
+        assert_eq!(
+            Ok(And(
+                Box::new(Or(
+                    Box::new(Comparison(
+                        Box::new(Operand(Argument::Name(simple_name("s")))),
+                        "==",
+                        Box::new(Operand(Argument::Literal("\"a && (b)\"")))
+                    )),
+                    Box::new(Operand(Argument::Literal("true")))
+                )),
+                Box::new(Operand(Argument::Name(Name {
+                    leading_dots: 1,
+                    segments: vec![Segment {
+                        name: "len",
+                        call: Some(vec![]),
+                    }],
+                    filters: vec![],
+                    text: ".len()",
+                })))
+            )),
+            expression("(s==\"a && (b)\" || true) && .len()")
+        );
+    }
+
+    #[test]
+    fn expression_matches_chained_calls() {
+        assert_eq!(
+            Ok(Expression::Comparison(
+                Box::new(Expression::Operand(Argument::Name(Name {
+                    leading_dots: 0,
+                    segments: vec![
+                        Segment {
+                            name: "user",
+                            call: Some(vec![]),
+                        },
+                        Segment {
+                            name: "age",
+                            call: None,
+                        },
+                    ],
+                    filters: vec![],
+                    text: "user().age",
+                }))),
+                ">=",
+                Box::new(Expression::Operand(Argument::Literal("18")))
+            )),
+            expression("user().age>=18")
+        );
+    }
+
+    #[test]
+    fn expression_mismatches() {
+        assert!(expression("").is_err());
+        assert!(expression("a &&").is_err());
+        assert!(expression("a = b").is_err());
+        assert!(expression("a < b < c").is_err());
+        assert!(expression("(a").is_err());
+        assert!(expression("\"unterminated").is_err());
+        assert!(expression("f(1").is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_if_tags() {
+        let if_name = Name {
+            text: "if",
+            ..simple_name("if")
+        };
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::If(
+                    if_name,
+                    Box::new(Expression::Operand(Argument::Name(simple_name("a"))))
+                )
+            )),
+            bart_tag("{{#if a}}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::ElseIf(
+                    "elif",
+                    Box::new(Expression::Operand(Argument::Literal("1")))
                 )
             )),
-            bart_tag("{{>ape}}")
+            bart_tag("{{ elif 1 }}tail", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::SectionOpener(SectionType::Iteration, simple_name("if"))
+            )),
+            bart_tag("{{#if}}tail", DEFAULT_DELIMITERS)
         );
     }
 
     #[test]
     fn bart_tag_matches_unescaped_interpolation() {
         assert_eq!(
-            Ok(("", Token::UnescapedInterpolation(simple_name("ape")))),
-            bart_tag("{{{ape}}}")
+            Ok(("", Token::UnescapedInterpolation(simple_name("ape"), None))),
+            bart_tag("{{{ape}}}", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_matches_comment() {
+        assert_eq!(
+            Ok(("tail", Token::Comment(" ape "))),
+            bart_tag("{{! ape }}tail", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_matches_multiline_comment() {
+        assert_eq!(
+            Ok(("", Token::Comment(" ape\nkatt\n"))),
+            bart_tag("{{! ape\nkatt\n}}", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_matches_set_delimiters() {
+        assert_eq!(
+            Ok((
+                "",
+                Token::SetDelimiters(Delimiters {
+                    opener: "<%",
+                    closer: "%>"
+                })
+            )),
+            bart_tag("{{=<% %>=}}", DEFAULT_DELIMITERS)
+        );
+    }
+
+    #[test]
+    fn bart_tag_denies_malformed_set_delimiters() {
+        assert!(bart_tag("{{=<%=}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{=<% %>}}", DEFAULT_DELIMITERS).is_err());
+        assert!(bart_tag("{{=<% = %>=}}", DEFAULT_DELIMITERS).is_err());
+    }
+
+    #[test]
+    fn bart_tag_matches_custom_delimiters() {
+        let delimiters = Delimiters {
+            opener: "<%",
+            closer: "%>",
+        };
+        assert_eq!(
+            Ok(("tail", Token::Interpolation(simple_name("ape"), None))),
+            bart_tag("<%ape%>tail", delimiters)
+        );
+        assert_eq!(
+            Ok((
+                "tail",
+                Token::UnescapedInterpolation(simple_name("ape"), None)
+            )),
+            bart_tag("<%{ape}%>tail", delimiters)
+        );
+    }
+
+    #[test]
+    fn bart_tag_allows_whitespace_control() {
+        assert_eq!(
+            Ok(("", Token::Interpolation(simple_name("ape"), None))),
+            bart_tag("{{~ape~}}", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("", Token::UnescapedInterpolation(simple_name("ape"), None))),
+            bart_tag("{{~{ape}~}}", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Ok(("", Token::SectionCloser(simple_name("ape")))),
+            bart_tag("{{~/ape}}", DEFAULT_DELIMITERS)
         );
     }
 
     #[test]
     fn error_on_invalid_tag() {
-        let res = bart_tag("{{+ape}}");
+        let res = bart_tag("{{+ape}}", DEFAULT_DELIMITERS);
         assert!(res.is_err());
     }
 
     #[test]
     fn error_on_invalid_tag_2() {
-        let res = bart_tag("{{ape-skrekk}}");
+        let res = bart_tag("{{ape-skrekk}}", DEFAULT_DELIMITERS);
         assert!(res.is_err());
     }
 
@@ -365,7 +1365,7 @@ mod tests {
     fn literal_reads_until_tag() {
         assert_eq!(
             Ok(("{{ape}}", Some(Token::Literal("head")))),
-            literal_text("head{{ape}}")
+            literal_text("head{{ape}}", DEFAULT_DELIMITERS)
         );
     }
 
@@ -373,28 +1373,31 @@ mod tests {
     fn literal_reads_until_end() {
         assert_eq!(
             Ok(("", Some(Token::Literal("head{ape}")))),
-            literal_text("head{ape}")
+            literal_text("head{ape}", DEFAULT_DELIMITERS)
         );
     }
 
     #[test]
     fn literal_returns_none_at_tag() {
-        assert_eq!(Ok(("{{ape}}", None)), literal_text("{{ape}}"));
+        assert_eq!(
+            Ok(("{{ape}}", None)),
+            literal_text("{{ape}}", DEFAULT_DELIMITERS)
+        );
     }
 
     #[test]
     fn literal_returns_none_at_end() {
-        assert_eq!(Ok(("", None)), literal_text(""));
+        assert_eq!(Ok(("", None)), literal_text("", DEFAULT_DELIMITERS));
     }
 
     #[test]
     fn template_with_tightly_packed_tags() {
-        let parsed = sequence("{{a}}{{b}}{{c}}").unwrap();
+        let parsed = sequence("{{a}}{{b}}{{c}}", DEFAULT_DELIMITERS).unwrap();
         assert_eq!(
             vec![
-                Interpolation(simple_name("a")),
-                Interpolation(simple_name("b")),
-                Interpolation(simple_name("c")),
+                Interpolation(simple_name("a"), None),
+                Interpolation(simple_name("b"), None),
+                Interpolation(simple_name("c"), None),
             ],
             parsed
         );
@@ -402,11 +1405,15 @@ mod tests {
 
     #[test]
     fn template_with_mixed_content() {
-        let parsed = sequence("Hello {{name}}! {{#list}}Welcome{{/list}}").unwrap();
+        let parsed = sequence(
+            "Hello {{name}}! {{#list}}Welcome{{/list}}",
+            DEFAULT_DELIMITERS,
+        )
+        .unwrap();
         assert_eq!(
             vec![
                 Literal("Hello "),
-                Interpolation(simple_name("name")),
+                Interpolation(simple_name("name"), None),
                 Literal("! "),
                 SectionOpener(SectionType::Iteration, simple_name("list")),
                 Literal("Welcome"),
@@ -417,25 +1424,92 @@ mod tests {
     }
 
     #[test]
-    fn tags_with_leading_dots() {
-        let parsed = sequence("{{.a}}{{..b}}{{...c}}").unwrap();
+    fn template_with_comment() {
+        let parsed = sequence("Hello {{! the name }}{{name}}", DEFAULT_DELIMITERS).unwrap();
         assert_eq!(
             vec![
-                Interpolation(Name {
-                    leading_dots: 1,
-                    segments: vec!["a"],
-                    function_call: false
-                }),
-                Interpolation(Name {
-                    leading_dots: 2,
-                    segments: vec!["b"],
-                    function_call: false
-                }),
-                Interpolation(Name {
-                    leading_dots: 3,
-                    segments: vec!["c"],
-                    function_call: false
+                Literal("Hello "),
+                Comment(" the name "),
+                Interpolation(simple_name("name"), None),
+            ],
+            parsed
+        );
+    }
+
+    #[test]
+    fn template_with_changing_delimiters() {
+        let parsed = sequence(
+            "{{a}}{{=<% %>=}}{{b}}<%c%><%={{ }}=%>{{d}}",
+            DEFAULT_DELIMITERS,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Interpolation(simple_name("a"), None),
+                SetDelimiters(Delimiters {
+                    opener: "<%",
+                    closer: "%>"
                 }),
+                Literal("{{b}}"),
+                Interpolation(simple_name("c"), None),
+                SetDelimiters(DEFAULT_DELIMITERS),
+                Interpolation(simple_name("d"), None),
+            ],
+            parsed
+        );
+    }
+
+    #[test]
+    fn template_with_whitespace_control() {
+        let parsed = sequence("a {{~b}} {{#c~}} d {{{e}~}}", DEFAULT_DELIMITERS).unwrap();
+        assert_eq!(
+            vec![
+                Literal("a "),
+                TrimWhitespace,
+                Interpolation(simple_name("b"), None),
+                Literal(" "),
+                SectionOpener(SectionType::Iteration, simple_name("c")),
+                TrimWhitespace,
+                Literal(" d "),
+                UnescapedInterpolation(simple_name("e"), None),
+                TrimWhitespace,
+            ],
+            parsed
+        );
+    }
+
+    #[test]
+    fn tags_with_leading_dots() {
+        let parsed = sequence("{{.a}}{{..b}}{{...c}}", DEFAULT_DELIMITERS).unwrap();
+        assert_eq!(
+            vec![
+                Interpolation(
+                    Name {
+                        leading_dots: 1,
+                        segments: fields(&["a"]),
+                        filters: vec![],
+                        text: ".a"
+                    },
+                    None
+                ),
+                Interpolation(
+                    Name {
+                        leading_dots: 2,
+                        segments: fields(&["b"]),
+                        filters: vec![],
+                        text: "..b"
+                    },
+                    None
+                ),
+                Interpolation(
+                    Name {
+                        leading_dots: 3,
+                        segments: fields(&["c"]),
+                        filters: vec![],
+                        text: "...c"
+                    },
+                    None
+                ),
             ],
             parsed
         );
@@ -443,19 +1517,27 @@ mod tests {
 
     #[test]
     fn tags_with_segmented_names() {
-        let parsed = sequence("{{a.b.c}}{{..b.c.d}}").unwrap();
+        let parsed = sequence("{{a.b.c}}{{..b.c.d}}", DEFAULT_DELIMITERS).unwrap();
         assert_eq!(
             vec![
-                Interpolation(Name {
-                    leading_dots: 0,
-                    segments: vec!["a", "b", "c"],
-                    function_call: false
-                }),
-                Interpolation(Name {
-                    leading_dots: 2,
-                    segments: vec!["b", "c", "d"],
-                    function_call: false
-                }),
+                Interpolation(
+                    Name {
+                        leading_dots: 0,
+                        segments: fields(&["a", "b", "c"]),
+                        filters: vec![],
+                        text: "a.b.c"
+                    },
+                    None
+                ),
+                Interpolation(
+                    Name {
+                        leading_dots: 2,
+                        segments: fields(&["b", "c", "d"]),
+                        filters: vec![],
+                        text: "..b.c.d"
+                    },
+                    None
+                ),
             ],
             parsed
         );
@@ -463,30 +1545,53 @@ mod tests {
 
     #[test]
     fn tags_with_segmentless_name() {
-        let parsed = sequence("{{.}}{{..}}").unwrap();
+        let parsed = sequence("{{.}}{{..}}", DEFAULT_DELIMITERS).unwrap();
         assert_eq!(
             vec![
-                Interpolation(Name {
-                    leading_dots: 1,
-                    segments: vec![],
-                    function_call: false
-                }),
-                Interpolation(Name {
-                    leading_dots: 2,
-                    segments: vec![],
-                    function_call: false
-                }),
+                Interpolation(
+                    Name {
+                        leading_dots: 1,
+                        segments: vec![],
+                        filters: vec![],
+                        text: "."
+                    },
+                    None
+                ),
+                Interpolation(
+                    Name {
+                        leading_dots: 2,
+                        segments: vec![],
+                        filters: vec![],
+                        text: ".."
+                    },
+                    None
+                ),
             ],
             parsed
         );
     }
 
+    #[test]
+    fn sequence_reports_error_offset() {
+        assert_eq!(
+            Err(Error {
+                offset: 9,
+                kind: ErrorKind::Mismatch
+            }),
+            sequence("{{a}}\n   {{+b}}", DEFAULT_DELIMITERS)
+        );
+        assert_eq!(
+            Err(Error {
+                offset: 3,
+                kind: ErrorKind::UnclosedTag
+            }),
+            sequence("a\n {{b", DEFAULT_DELIMITERS)
+        );
+    }
+
     #[test]
     fn tags_with_segmentless_name_missing_dots() {
-        match sequence("{{}}") {
-            Ok(_) => panic!(),
-            Err(_) => (),
-        }
+        assert!(sequence("{{}}", DEFAULT_DELIMITERS).is_err());
     }
 
     #[test]
@@ -499,6 +1604,23 @@ mod tests {
         assert_eq!(Ok(vec!["ape", "katt"]), segmented_name("ape.katt"));
     }
 
+    #[test]
+    fn segmented_name_allows_keywords_and_raw_identifiers() {
+        assert_eq!(Ok(vec!["type", "r#match"]), segmented_name("type.r#match"));
+    }
+
+    #[test]
+    fn names_deny_keywords_that_cannot_be_raw_identifiers() {
+        for keyword in ["self", "Self", "super", "crate", "_"] {
+            assert!(name(&format!("a.{}", keyword)).is_err());
+            assert!(name(&format!(".{}", keyword)).is_err());
+            assert!(name(&format!("a.{}()", keyword)).is_err());
+            assert!(segmented_name(keyword).is_err());
+        }
+        assert!(sequence("{{a.self}}", DEFAULT_DELIMITERS).is_err());
+        assert!(sequence("{{#items}}{{._}}{{/items}}", DEFAULT_DELIMITERS).is_err());
+    }
+
     #[test]
     fn simple_segmented_name_denies_leading_dots() {
         assert!(segmented_name(".ape.katt").is_err());
@@ -526,8 +1648,9 @@ mod tests {
                 "",
                 Name {
                     leading_dots: 1,
-                    segments: vec!["ape"],
-                    function_call: false
+                    segments: fields(&["ape"]),
+                    filters: vec![],
+                    text: ".ape"
                 }
             )),
             name(".ape")
@@ -541,8 +1664,9 @@ mod tests {
                 "",
                 Name {
                     leading_dots: 0,
-                    segments: vec!["ape", "2", "skrekk"],
-                    function_call: false
+                    segments: fields(&["ape", "2", "skrekk"]),
+                    filters: vec![],
+                    text: "ape.2.skrekk"
                 }
             )),
             name("ape.2.skrekk")
@@ -557,7 +1681,8 @@ mod tests {
                 Name {
                     leading_dots: 1,
                     segments: vec![],
-                    function_call: false
+                    filters: vec![],
+                    text: "."
                 }
             )),
             name(".")
@@ -576,8 +1701,12 @@ mod tests {
                 "",
                 Name {
                     leading_dots: 0,
-                    segments: vec!["fun"],
-                    function_call: true
+                    segments: vec![Segment {
+                        name: "fun",
+                        call: Some(vec![]),
+                    }],
+                    filters: vec![],
+                    text: "fun()"
                 }
             )),
             name("fun()")
@@ -591,11 +1720,100 @@ mod tests {
                 "",
                 Name {
                     leading_dots: 0,
-                    segments: vec!["fun"],
-                    function_call: true
+                    segments: vec![Segment {
+                        name: "fun",
+                        call: Some(vec![]),
+                    }],
+                    filters: vec![],
+                    text: "fun ()"
                 }
             )),
             name("fun () ")
         );
     }
+
+    #[test]
+    fn function_call_name_with_arguments() {
+        assert_eq!(
+            Ok((
+                "",
+                Name {
+                    leading_dots: 1,
+                    segments: vec![Segment {
+                        name: "fun",
+                        call: Some(vec![
+                            Argument::Literal("\"a, (b)\""),
+                            Argument::Literal("-2.5"),
+                            Argument::Name(Name {
+                                leading_dots: 2,
+                                segments: vec![Segment {
+                                    name: "x",
+                                    call: Some(vec![]),
+                                }],
+                                filters: vec![],
+                                text: "..x()",
+                            }),
+                        ]),
+                    }],
+                    filters: vec![],
+                    text: ".fun(\"a, (b)\", -2.5, ..x())"
+                }
+            )),
+            name(".fun(\"a, (b)\", -2.5, ..x())")
+        );
+        assert!(name("fun(a b)").is_err());
+        assert!(name("fun(a").is_err());
+    }
+
+    #[test]
+    fn function_calls_in_intermediate_segments() {
+        assert_eq!(
+            Ok((
+                "",
+                Name {
+                    leading_dots: 0,
+                    segments: vec![
+                        Segment {
+                            name: "user",
+                            call: Some(vec![]),
+                        },
+                        Segment {
+                            name: "profile",
+                            call: Some(vec![Argument::Literal("\"a.b\"")]),
+                        },
+                        Segment {
+                            name: "name",
+                            call: None,
+                        },
+                    ],
+                    filters: vec![],
+                    text: "user().profile(\"a.b\").name"
+                }
+            )),
+            name("user().profile(\"a.b\").name")
+        );
+        assert!(name("a().").is_err());
+        assert!(name("a().0()").is_err());
+        assert!(name("a()b").is_err());
+    }
+
+    #[test]
+    fn calling_the_value_itself() {
+        assert_eq!(
+            Ok((
+                "",
+                Name {
+                    leading_dots: 1,
+                    segments: vec![Segment {
+                        name: "",
+                        call: Some(vec![]),
+                    }],
+                    filters: vec![],
+                    text: ".()"
+                }
+            )),
+            name(".()")
+        );
+        assert!(name("()").is_err());
+    }
 }
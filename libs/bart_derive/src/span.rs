@@ -0,0 +1,204 @@
+use crate::diagnostics::{offset_of, Diagnostic};
+use proc_macro2::{Literal, Span, TokenStream};
+use quote::*;
+use std::ops::Range;
+
+/// A template, along with what is needed to point at parts of it in error
+/// messages
+pub struct Source<'a> {
+    pub path: &'a str,
+    pub template: &'a str,
+    // The string literal of an inline template
+    pub literal: Option<Literal>,
+    // The parent templates that were flattened into this one, which text
+    // may also be a slice of
+    pub parents: Vec<Source<'a>>,
+}
+
+impl<'a> Source<'a> {
+    /// Whether `text` is a slice of the template
+    pub fn contains(&self, text: &str) -> bool {
+        let start = self.template.as_ptr() as usize;
+        let pos = text.as_ptr() as usize;
+        pos >= start && pos + text.len() <= start + self.template.len()
+    }
+
+    // The template that `text` is a slice of, among this and its parents
+    fn containing(&self, text: &str) -> &Source<'a> {
+        self.parents
+            .iter()
+            .find(|parent| parent.contains(text))
+            .unwrap_or(self)
+    }
+
+    /// The span of `text`, which must be a slice of the template or one of
+    /// its parents. Only inline templates have a span in the user's source,
+    /// so for template files this is the span of the derive.
+    pub fn span_of(&self, text: &str) -> Span {
+        let source = self.containing(text);
+        match source.literal {
+            Some(ref literal) => {
+                let start = offset_of(source.template, text);
+                subspan(literal, start..start + text.len())
+            }
+            None => Span::call_site(),
+        }
+    }
+
+    /// `compile_error!(...)` with the message, located at `text`, which may
+    /// be in a parent template
+    pub fn error_at(&self, text: &str, message: String) -> TokenStream {
+        let source = self.containing(text);
+        source.compile_error(&Diagnostic {
+            offset: offset_of(source.template, text),
+            message,
+            notes: vec![],
+        })
+    }
+
+    /// `compile_error!(...)` with the rendered diagnostic, located at the
+    /// problem
+    pub fn compile_error(&self, diagnostic: &Diagnostic) -> TokenStream {
+        let span = match self.literal {
+            Some(ref literal) => {
+                let start = diagnostic.offset;
+                let end = self.template[start..]
+                    .chars()
+                    .next()
+                    .map_or(start, |ch| start + ch.len_utf8());
+                subspan(literal, start..end)
+            }
+            None => Span::call_site(),
+        };
+        let message = diagnostic.render(self.path, self.template);
+        quote_spanned! { span=> compile_error!(#message) }
+    }
+}
+
+// The byte offset in the source code of a string literal, such as
+// `"a\tb"`, of the given byte offset in the value of the string
+fn source_offset(literal_source: &str, value_offset: usize) -> usize {
+    if let Some(raw) = literal_source.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return 1 + hashes + 1 + value_offset;
+    }
+
+    let mut value_pos = 0;
+    let mut chars = literal_source.char_indices().skip(1).peekable();
+
+    while let Some((pos, ch)) = chars.next() {
+        let line_continuation =
+            ch == '\\' && matches!(chars.peek(), Some((_, '\n')) | Some((_, '\r')));
+        if value_pos >= value_offset && !line_continuation {
+            return pos;
+        }
+
+        if ch != '\\' {
+            value_pos += ch.len_utf8();
+            continue;
+        }
+
+        match chars.next() {
+            Some((_, 'x')) => {
+                chars.nth(1);
+                value_pos += 1;
+            }
+            Some((_, 'u')) => {
+                let mut code = String::new();
+                for (_, ch) in chars.by_ref() {
+                    match ch {
+                        '{' => (),
+                        '}' => break,
+                        ch => code.push(ch),
+                    }
+                }
+                value_pos += u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .map_or(1, char::len_utf8);
+            }
+            Some((_, '\n')) | Some((_, '\r')) => {
+                while chars.next_if(|&(_, ch)| ch.is_whitespace()).is_some() {}
+            }
+            _ => value_pos += 1,
+        }
+    }
+
+    literal_source.len() - 1
+}
+
+// The span of the given byte range of the value of the string literal.
+// Only some compilers support spans inside literals, so this falls back to
+// the span of the entire literal.
+fn subspan(literal: &Literal, value_range: Range<usize>) -> Span {
+    let source = literal.to_string();
+    let start = source_offset(&source, value_range.start);
+    let end = source_offset(&source, value_range.end);
+
+    literal
+        .subspan(start..end)
+        .unwrap_or_else(|| literal.span())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_maps_offsets_in_plain_strings() {
+        assert_eq!(1, source_offset(r#""abc""#, 0));
+        assert_eq!(3, source_offset(r#""abc""#, 2));
+    }
+
+    #[test]
+    fn it_maps_offsets_past_escapes() {
+        let source = r#""a\n\"{{x}}""#;
+        assert_eq!(6, source_offset(source, 3));
+        assert_eq!(8, source_offset(source, 5));
+    }
+
+    #[test]
+    fn it_maps_offsets_past_unicode_and_byte_escapes() {
+        assert_eq!(11, source_offset(r#""\u{e6}\x41b""#, 3));
+    }
+
+    #[test]
+    fn it_maps_offsets_past_line_continuations() {
+        assert_eq!(7, source_offset("\"a\\\n   b\"", 1));
+    }
+
+    #[test]
+    fn it_maps_offsets_in_raw_strings() {
+        assert_eq!(5, source_offset(r###"r#"a"b"#"###, 2));
+    }
+
+    fn inline_source<'a>(template: &'a str, literal: &str) -> Source<'a> {
+        Source {
+            path: "test",
+            template,
+            literal: Some(literal.parse().unwrap()),
+            parents: vec![],
+        }
+    }
+
+    #[test]
+    fn it_spans_text_in_inline_templates() {
+        let template = "a\n{{x}}";
+        let source = inline_source(template, r#""a\n{{x}}""#);
+        assert_eq!(4..9, source.span_of(&template[2..]).byte_range());
+    }
+
+    #[test]
+    fn it_spans_errors_at_the_offending_character() {
+        let template = "a\n{{x}}";
+        let source = inline_source(template, r#""a\n{{x}}""#);
+        let error = source.compile_error(&Diagnostic {
+            offset: 4,
+            message: "Problem".to_owned(),
+            notes: vec![],
+        });
+
+        let span = error.into_iter().next().unwrap().span();
+        assert_eq!(6..7, span.byte_range());
+    }
+}
@@ -1,8 +1,74 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Name<'a> {
     pub leading_dots: u32,
-    pub segments: Vec<&'a str>,
-    pub function_call: bool,
+    pub segments: Vec<Segment<'a>>,
+
+    // Filters applied to the value in interpolations, as in {{name | upper}}
+    pub filters: Vec<Filter<'a>>,
+
+    // The name as written in the template, for locating it in error messages
+    pub text: &'a str,
+}
+
+impl<'a> Name<'a> {
+    pub fn refers_to(&self, other: &Name) -> bool {
+        // File names, as of parent templates, are compared as written
+        let is_file_name = |name: &Name| name.leading_dots == 0 && name.segments.is_empty();
+        if is_file_name(self) || is_file_name(other) {
+            return self.text == other.text;
+        }
+
+        self.leading_dots == other.leading_dots && self.segments == other.segments
+    }
+}
+
+// A segment of a dotted name, which may be a function call, as in `profile()`
+// in {{user.profile().name}}. A call without a name, as in {{.()}}, calls
+// the value itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub name: &'a str,
+
+    // The arguments of a function call, as in {{price_in(currency)}}
+    pub call: Option<Vec<Argument<'a>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter<'a> {
+    pub name: &'a str,
+    pub arguments: Vec<Argument<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Argument<'a> {
+    // A Rust literal, such as "text" or 20
+    Literal(&'a str),
+    Name(Name<'a>),
+}
+
+// What a partial is included with: the value of a name, as in {{>row item}},
+// or named arguments, as in {{>button label="Save" kind=.primary}}, which make
+// up a scope of their own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartialRoot<'a> {
+    Name(Name<'a>),
+    Arguments(Vec<(&'a str, Argument<'a>)>),
+}
+
+// A boolean expression, as in {{#if count > 0 && !done}}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression<'a> {
+    // A literal, or a name, which is tested with `bart::Conditional`
+    Operand(Argument<'a>),
+
+    // A comparison with the operator as written, such as `status == "active"`.
+    // The operands are compared as they are, so in `!done == false`, `!`
+    // applies to the value of `done`.
+    Comparison(Box<Expression<'a>>, &'a str, Box<Expression<'a>>),
+
+    Not(Box<Expression<'a>>),
+    And(Box<Expression<'a>>, Box<Expression<'a>>),
+    Or(Box<Expression<'a>>, Box<Expression<'a>>),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -23,21 +89,72 @@ pub enum SectionType {
     Scope,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delimiters<'a> {
+    pub opener: &'a str,
+    pub closer: &'a str,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Token<'a> {
     Literal(&'a str),
-    Interpolation(Name<'a>),
-    UnescapedInterpolation(Name<'a>),
+    // The name and an optional format spec, as in {{price:.2}}
+    Interpolation(Name<'a>, Option<&'a str>),
+    UnescapedInterpolation(Name<'a>, Option<&'a str>),
     SectionOpener(SectionType, Name<'a>),
     SectionCloser(Name<'a>),
-    PartialInclude(&'a str, Name<'a>),
+    PartialInclude(&'a str, PartialRoot<'a>),
+    Comment(&'a str),
+    SetDelimiters(Delimiters<'a>),
+
+    // {{:sep}}, starting the separator clause of an iteration section
+    Separator(&'a str),
+
+    // {{else}}, starting the clause rendered when a section is not
+    Else(&'a str),
+
+    // {{#if expression}}, with the `if` keyword as the name to close
+    If(Name<'a>, Box<Expression<'a>>),
+
+    // {{elif expression}}, with the `elif` keyword
+    ElseIf(&'a str, Box<Expression<'a>>),
+
+    // {{#match name}}, with the `match` keyword as the name to close
+    Match(Name<'a>, Box<Name<'a>>),
+
+    // {{:Variant}}, starting a match arm, with the path of the variant
+    Arm(&'a str),
+
+    // {{<layout.html}}, with the name of the parent template file. The name
+    // has no segments, as it is not the name of a field.
+    Parent(Name<'a>),
+
+    // {{$content}}, opening a block of a parent template
+    Block(Name<'a>),
+
+    // {{*define row}}, with the `define` keyword as the name to close and
+    // the name of the partial being defined
+    Define(Name<'a>, &'a str),
+
+    // A whitespace control marker, as in {{~name~}}
+    TrimWhitespace,
 }
 
 #[cfg(test)]
 pub fn simple_name(name: &'static str) -> Name<'static> {
     Name {
         leading_dots: 0,
-        segments: vec![name],
-        function_call: false,
+        segments: fields(&[name]),
+        filters: vec![],
+        text: name,
     }
 }
+
+// The segments of a name without function calls, as in {{a.b}}
+#[cfg(test)]
+pub fn fields(names: &[&'static str]) -> Vec<Segment<'static>> {
+    names
+        .iter()
+        .map(|&name| Segment { name, call: None })
+        .collect()
+}
@@ -0,0 +1,305 @@
+use crate::ast::Ast;
+use crate::diagnostics::{offset_of, Diagnostic};
+use crate::token::{Argument, Expression, Name, PartialRoot};
+
+// Optimal string alignment distance: Levenshtein distance where swapping two
+// adjacent characters, as in `nmae`, counts as a single edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+fn closest<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    // Allow about one typo per three characters, like rustc does
+    let max_distance = std::cmp::max(name.chars().count(), 3) / 3;
+
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+fn top_level_names<'a, 'b>(node: &'b Ast<'a>, names: &mut Vec<&'b Name<'a>>) {
+    match *node {
+        Ast::Literal(_) => (),
+        Ast::Interpolation(ref name, _) | Ast::UnescapedInterpolation(ref name, _) => {
+            push_name(name, names);
+        }
+        Ast::Sequence(ref seq) => {
+            for node in seq {
+                top_level_names(node, names);
+            }
+        }
+        Ast::Iteration {
+            ref name,
+            ref nested,
+            ref separator,
+            ref otherwise,
+        } => {
+            push_name(name, names);
+            top_level_names(nested, names);
+            for clause in separator.iter().chain(otherwise) {
+                top_level_names(clause, names);
+            }
+        }
+        Ast::NegativeIteration {
+            ref name,
+            ref nested,
+        } => {
+            push_name(name, names);
+            top_level_names(nested, names);
+        }
+        Ast::Conditional {
+            ref name,
+            ref nested,
+            ref otherwise,
+        }
+        | Ast::NegativeConditional {
+            ref name,
+            ref nested,
+            ref otherwise,
+        }
+        | Ast::Scope {
+            ref name,
+            ref nested,
+            ref otherwise,
+        } => {
+            push_name(name, names);
+            top_level_names(nested, names);
+            if let Some(ref otherwise) = *otherwise {
+                top_level_names(otherwise, names);
+            }
+        }
+        Ast::If {
+            ref branches,
+            ref otherwise,
+        } => {
+            for (condition, nested) in branches {
+                expression_names(condition, names);
+                top_level_names(nested, names);
+            }
+            if let Some(ref otherwise) = *otherwise {
+                top_level_names(otherwise, names);
+            }
+        }
+        Ast::Match {
+            ref name,
+            ref arms,
+            ref otherwise,
+        } => {
+            push_name(name, names);
+            for (_, nested) in arms {
+                top_level_names(nested, names);
+            }
+            if let Some(ref otherwise) = *otherwise {
+                top_level_names(otherwise, names);
+            }
+        }
+        Ast::PartialInclude { ref root, .. } => match *root {
+            PartialRoot::Name(ref root) => push_name(root, names),
+            PartialRoot::Arguments(ref arguments) => {
+                for (_, argument) in arguments {
+                    push_argument(argument, names);
+                }
+            }
+        },
+        // Names in a partial definition are relative to the root it is
+        // included with
+        Ast::Define { .. } => {}
+        Ast::Block { ref nested, .. } => top_level_names(nested, names),
+        Ast::Parent { ref blocks, .. } => {
+            for (_, nested) in blocks {
+                top_level_names(nested, names);
+            }
+        }
+    }
+}
+
+fn expression_names<'a, 'b>(expression: &'b Expression<'a>, names: &mut Vec<&'b Name<'a>>) {
+    match *expression {
+        Expression::Operand(ref operand) => push_argument(operand, names),
+        Expression::Not(ref expression) => expression_names(expression, names),
+        Expression::Comparison(ref lhs, _, ref rhs)
+        | Expression::And(ref lhs, ref rhs)
+        | Expression::Or(ref lhs, ref rhs) => {
+            expression_names(lhs, names);
+            expression_names(rhs, names);
+        }
+    }
+}
+
+// The name along with the names in its arguments, as in
+// {{price_in(currency) | default(fallback)}}
+fn push_name<'a, 'b>(name: &'b Name<'a>, names: &mut Vec<&'b Name<'a>>) {
+    names.push(name);
+    let call_arguments = name.segments.iter().flat_map(|x| x.call.iter().flatten());
+    let filter_arguments = name.filters.iter().flat_map(|x| &x.arguments);
+    for argument in call_arguments.chain(filter_arguments) {
+        push_argument(argument, names);
+    }
+}
+
+fn push_argument<'a, 'b>(argument: &'b Argument<'a>, names: &mut Vec<&'b Name<'a>>) {
+    if let Argument::Name(ref name) = *argument {
+        push_name(name, names);
+    }
+}
+
+/// Check that the names in the template which refer to the root of the
+/// template, such as `{{name}}`, are fields of the struct. Method calls like
+/// `{{name()}}` and nested paths like `{{name.len()}}` are left for rustc to
+/// check. Fields that are available through `Deref` are not known here, so
+/// the check can be turned off with #[template_skip_field_check].
+pub fn check_fields(
+    template: &str,
+    ast: &Ast,
+    type_name: &str,
+    fields: &[String],
+) -> Vec<Diagnostic> {
+    let mut names = vec![];
+    top_level_names(ast, &mut names);
+
+    names
+        .into_iter()
+        .filter(|name| name.leading_dots == 0)
+        .filter(|name| name.segments.len() == 1 && name.segments[0].call.is_none())
+        .filter(|name| !name.segments[0].name.starts_with('@'))
+        .filter(|name| {
+            // Fields are named without `r#`, as in `{{type}}`
+            let field = name.segments[0].name;
+            let field = field.strip_prefix("r#").unwrap_or(field);
+            !fields.iter().any(|x| x == field)
+        })
+        .map(|name| {
+            let field = name.segments[0].name;
+            let mut message = format!("No field `{}` on type `{}`", field, type_name);
+            match closest(field, fields) {
+                Some(suggestion) => message.push_str(&format!(". Did you mean `{}`?", suggestion)),
+                None => message.push_str(
+                    ". If it is available through `Deref`, add #[template_skip_field_check]",
+                ),
+            }
+            Diagnostic {
+                offset: offset_of(template, name.text),
+                message,
+                notes: vec![],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::scanner::{sequence, DEFAULT_DELIMITERS};
+
+    fn check(template: &str) -> Vec<Diagnostic> {
+        let ast = parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap();
+        let fields = vec!["name".to_owned(), "items".to_owned()];
+        check_fields(template, &ast, "Test", &fields)
+    }
+
+    #[test]
+    fn it_computes_edit_distance() {
+        assert_eq!(0, edit_distance("name", "name"));
+        assert_eq!(1, edit_distance("nmae", "name"));
+        assert_eq!(2, edit_distance("nmea", "name"));
+        assert_eq!(1, edit_distance("nam", "name"));
+        assert_eq!(4, edit_distance("", "name"));
+    }
+
+    #[test]
+    fn it_accepts_known_fields() {
+        assert_eq!(
+            Vec::<Diagnostic>::new(),
+            check("{{name}}{{#items}}{{.}}{{name.len()}}{{/items}}")
+        );
+    }
+
+    #[test]
+    fn it_leaves_method_calls_and_nested_paths_to_rustc() {
+        assert_eq!(
+            Vec::<Diagnostic>::new(),
+            check("{{len()}}{{title.len()}}{{title.text}}")
+        );
+    }
+
+    #[test]
+    fn it_leaves_loop_variables_to_the_generator() {
+        assert_eq!(
+            Vec::<Diagnostic>::new(),
+            check("{{#items}}{{@index}}{{/items}}")
+        );
+    }
+
+    #[test]
+    fn it_checks_names_in_arguments() {
+        assert_eq!(
+            vec![Diagnostic {
+                offset: 12,
+                message: "No field `nmae` on type `Test`. Did you mean `name`?".to_owned(),
+                notes: vec![],
+            }],
+            check("{{items.get(nmae, \"x\")}}")
+        );
+    }
+
+    #[test]
+    fn it_accepts_raw_identifiers() {
+        let template = "{{type}}{{r#type}}";
+        let ast = parse(sequence(template, DEFAULT_DELIMITERS).unwrap()).unwrap();
+        assert_eq!(
+            Vec::<Diagnostic>::new(),
+            check_fields(template, &ast, "Test", &["type".to_owned()])
+        );
+    }
+
+    #[test]
+    fn it_mentions_skipping_the_check_without_suggestion() {
+        assert_eq!(
+            vec![Diagnostic {
+                offset: 2,
+                message: "No field `title` on type `Test`. If it is available through \
+                    `Deref`, add #[template_skip_field_check]"
+                    .to_owned(),
+                notes: vec![],
+            }],
+            check("{{title}}")
+        );
+    }
+
+    #[test]
+    fn it_suggests_closest_field() {
+        assert_eq!(
+            vec![Diagnostic {
+                offset: 7,
+                message: "No field `nmae` on type `Test`. Did you mean `name`?".to_owned(),
+                notes: vec![],
+            }],
+            check("<p>\n{{#nmae}}{{/nmae}}")
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use crate::token::*;
+
+fn is_standalone_candidate(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::SectionOpener(..)
+            | Token::SectionCloser(_)
+            | Token::PartialInclude(..)
+            | Token::Comment(_)
+            | Token::SetDelimiters(_)
+            | Token::Separator(_)
+            | Token::Else(_)
+            | Token::If(..)
+            | Token::ElseIf(..)
+            | Token::Match(..)
+            | Token::Arm(_)
+            | Token::Parent(_)
+            | Token::Block(_)
+            | Token::Define(..)
+    )
+}
+
+fn is_blank(text: &str) -> bool {
+    text.chars().all(|ch| ch == ' ' || ch == '\t')
+}
+
+// The number of bytes at the end of the literal preceding a tag that make up
+// the indentation of the tag, if the tag is the first thing on its line.
+fn indentation(tokens: &[Token], index: usize) -> Option<usize> {
+    if index == 0 {
+        return Some(0);
+    }
+
+    match tokens[index - 1] {
+        Token::Literal(text) => {
+            let indentation = match text.rfind('\n') {
+                Some(newline) => &text[newline + 1..],
+                None if index == 1 => text,
+                None => return None,
+            };
+            match is_blank(indentation) {
+                true => Some(indentation.len()),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The number of bytes at the start of the literal following a tag that make
+// up the rest of its line, including the newline, if the tag is the last
+// thing on its line.
+fn line_ending(tokens: &[Token], index: usize) -> Option<usize> {
+    match tokens.get(index + 1) {
+        None => Some(0),
+        Some(&Token::Literal(text)) => match text.find('\n') {
+            Some(newline) => match is_blank(text[..newline].trim_end_matches('\r')) {
+                true => Some(newline + 1),
+                false => None,
+            },
+            None if index + 2 == tokens.len() && is_blank(text) => Some(text.len()),
+            None => None,
+        },
+        Some(_) => None,
+    }
+}
+
+/// Remove lines that contain nothing but a single section tag, separator,
+/// else, elif or match arm tag, partial include or definition, parent or
+/// block tag, comment or set delimiter tag and whitespace, as in Mustache.
+pub fn strip_standalone_lines(tokens: Vec<Token<'_>>) -> Vec<Token<'_>> {
+    // Standalone tags are recognized on the untrimmed literals, so trimming
+    // is deferred until all tags have been inspected
+    let mut trim = vec![(0, 0); tokens.len()];
+
+    for index in 0..tokens.len() {
+        if !is_standalone_candidate(&tokens[index]) {
+            continue;
+        }
+
+        if let (Some(indentation), Some(line_ending)) =
+            (indentation(&tokens, index), line_ending(&tokens, index))
+        {
+            if index > 0 {
+                trim[index - 1].1 = indentation;
+            }
+            if index + 1 < tokens.len() {
+                trim[index + 1].0 = line_ending;
+            }
+        }
+    }
+
+    tokens
+        .into_iter()
+        .zip(trim)
+        .filter_map(|(token, (start, end))| match token {
+            Token::Literal(text) => match &text[start..text.len() - end] {
+                "" => None,
+                text => Some(Token::Literal(text)),
+            },
+            token => Some(token),
+        })
+        .collect()
+}
+
+/// Trim all whitespace from the literals next to tags that are marked for it,
+/// as in `{{~name~}}`, and remove the markers.
+pub fn trim_marked_whitespace(tokens: Vec<Token<'_>>) -> Vec<Token<'_>> {
+    let mut trimmed = Vec::with_capacity(tokens.len());
+    let mut trim_start = false;
+
+    for token in tokens {
+        match token {
+            Token::TrimWhitespace => {
+                if let Some(Token::Literal(text)) = trimmed.last_mut() {
+                    *text = text.trim_end();
+                }
+                trim_start = true;
+                continue;
+            }
+            Token::Literal(text) if trim_start => trimmed.push(Token::Literal(text.trim_start())),
+            token => trimmed.push(token),
+        }
+        trim_start = false;
+    }
+
+    trimmed.retain(|token| token != &Token::Literal(""));
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Token::*;
+    use super::*;
+
+    #[test]
+    fn it_strips_standalone_section_lines() {
+        assert_eq!(
+            vec![
+                Literal("<ul>\n"),
+                SectionOpener(SectionType::Iteration, simple_name("a")),
+                Literal("  <li>\n"),
+                SectionCloser(simple_name("a")),
+                Literal("</ul>\n"),
+            ],
+            strip_standalone_lines(vec![
+                Literal("<ul>\n  "),
+                SectionOpener(SectionType::Iteration, simple_name("a")),
+                Literal("\n  <li>\n  "),
+                SectionCloser(simple_name("a")),
+                Literal("  \n</ul>\n"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_strips_standalone_lines_at_the_edges() {
+        assert_eq!(
+            vec![Comment(" a "), Literal("text\n"), Comment(" b ")],
+            strip_standalone_lines(vec![Comment(" a "), Literal("\ntext\n  "), Comment(" b "),])
+        );
+    }
+
+    #[test]
+    fn it_strips_adjacent_standalone_lines() {
+        assert_eq!(
+            vec![
+                SectionOpener(SectionType::Conditional, simple_name("a")),
+                SectionCloser(simple_name("a")),
+            ],
+            strip_standalone_lines(vec![
+                SectionOpener(SectionType::Conditional, simple_name("a")),
+                Literal("\r\n"),
+                SectionCloser(simple_name("a")),
+                Literal("\n"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_keeps_lines_with_other_content() {
+        let tokens = || {
+            vec![
+                Literal("text "),
+                SectionOpener(SectionType::Iteration, simple_name("a")),
+                Literal("\n"),
+                Interpolation(simple_name("b"), None),
+                SectionCloser(simple_name("a")),
+                Literal("\n"),
+            ]
+        };
+        assert_eq!(tokens(), strip_standalone_lines(tokens()));
+    }
+
+    #[test]
+    fn it_trims_whitespace_next_to_markers() {
+        assert_eq!(
+            vec![
+                Literal("a"),
+                Interpolation(simple_name("b"), None),
+                Literal(" "),
+                SectionOpener(SectionType::Iteration, simple_name("c")),
+                Literal("d \n"),
+                SectionCloser(simple_name("c")),
+            ],
+            trim_marked_whitespace(vec![
+                Literal("a \n "),
+                TrimWhitespace,
+                Interpolation(simple_name("b"), None),
+                Literal(" "),
+                SectionOpener(SectionType::Iteration, simple_name("c")),
+                TrimWhitespace,
+                Literal("\n  d \n"),
+                SectionCloser(simple_name("c")),
+                TrimWhitespace,
+                Literal("\n"),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_keeps_lines_with_interpolations() {
+        let tokens = || {
+            vec![
+                Literal("a\n"),
+                Interpolation(simple_name("b"), None),
+                Literal("\nc"),
+            ]
+        };
+        assert_eq!(tokens(), strip_standalone_lines(tokens()));
+    }
+}
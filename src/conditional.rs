@@ -14,13 +14,13 @@ impl<T> Conditional for Vec<T> {
     }
 }
 
-impl<'a, T> Conditional for &'a [T] {
+impl<T> Conditional for &[T] {
     fn val(&self) -> bool {
         !self.is_empty()
     }
 }
 
-impl<'a, T: Conditional> Conditional for &'a T {
+impl<T: Conditional> Conditional for &T {
     fn val(&self) -> bool {
         (*self).val()
     }
@@ -0,0 +1,197 @@
+//! The built-in filters, for use in templates as in `{{name | upper}}`.
+//!
+//! A filter is a function taking a reference to the value as its first
+//! argument, followed by the arguments given in the template.
+
+use std::fmt::{self, Display, Write};
+
+pub fn upper<T: Display + ?Sized>(value: &T) -> String {
+    value.to_string().to_uppercase()
+}
+
+pub fn lower<T: Display + ?Sized>(value: &T) -> String {
+    value.to_string().to_lowercase()
+}
+
+pub fn trim<T: Display + ?Sized>(value: &T) -> String {
+    value.to_string().trim().to_owned()
+}
+
+/// Uppercase the first character and lowercase the rest
+pub fn capitalize<T: Display + ?Sized>(value: &T) -> String {
+    let value = value.to_string();
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => value,
+    }
+}
+
+/// Shorten the value to at most `length` characters, ending with `…` if it
+/// was shortened
+pub fn truncate<T: Display + ?Sized>(value: &T, length: usize) -> String {
+    let value = value.to_string();
+    if value.chars().count() <= length {
+        return value;
+    }
+
+    let mut truncated: String = value.chars().take(length.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Values which might be missing, for the `default` filter
+pub trait Optional {
+    type Value: Display + ?Sized;
+
+    fn value(&self) -> Option<&Self::Value>;
+}
+
+impl<T: Display> Optional for Option<T> {
+    type Value = T;
+
+    fn value(&self) -> Option<&T> {
+        self.as_ref()
+    }
+}
+
+impl Optional for str {
+    type Value = str;
+
+    fn value(&self) -> Option<&str> {
+        match self.is_empty() {
+            true => None,
+            false => Some(self),
+        }
+    }
+}
+
+impl Optional for String {
+    type Value = str;
+
+    fn value(&self) -> Option<&str> {
+        self.as_str().value()
+    }
+}
+
+impl<T: Optional + ?Sized> Optional for &T {
+    type Value = T::Value;
+
+    fn value(&self) -> Option<&T::Value> {
+        (**self).value()
+    }
+}
+
+pub enum Defaulted<'a, T: ?Sized, U> {
+    Value(&'a T),
+    Fallback(U),
+}
+
+impl<'a, T: Display + ?Sized, U: Display> Display for Defaulted<'a, T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Defaulted::Value(value) => value.fmt(f),
+            Defaulted::Fallback(ref fallback) => fallback.fmt(f),
+        }
+    }
+}
+
+/// Use `fallback` when the value is `None` or an empty string
+pub fn default<T: Optional + ?Sized, U: Display>(
+    value: &T,
+    fallback: U,
+) -> Defaulted<'_, T::Value, U> {
+    match value.value() {
+        Some(value) => Defaulted::Value(value),
+        None => Defaulted::Fallback(fallback),
+    }
+}
+
+/// Join the items of a collection, with `separator` between them
+pub fn join<'a, I, S>(values: &'a I, separator: S) -> String
+where
+    I: ?Sized,
+    &'a I: IntoIterator,
+    <&'a I as IntoIterator>::Item: Display,
+    S: Display,
+{
+    let mut joined = String::new();
+    for (index, value) in values.into_iter().enumerate() {
+        if index > 0 {
+            write!(joined, "{}", separator).unwrap();
+        }
+        write!(joined, "{}", value).unwrap();
+    }
+    joined
+}
+
+/// Percent-encode all but the unreserved characters of RFC 3986
+pub fn urlencode<T: Display + ?Sized>(value: &T) -> String {
+    let mut encoded = String::new();
+    for byte in value.to_string().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+/// `s` unless the count is one, as in `{{count}} item{{count | pluralize}}`
+pub fn pluralize<T: PartialEq + From<u8>>(count: &T) -> &'static str {
+    match *count == T::from(1) {
+        true => "",
+        false => "s",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_changes_case() {
+        assert_eq!("ABC", upper("aBc"));
+        assert_eq!("abc", lower("aBc"));
+        assert_eq!("Abc", capitalize("aBC"));
+        assert_eq!("", capitalize(""));
+    }
+
+    #[test]
+    fn it_truncates() {
+        assert_eq!("abc", truncate("abc", 3));
+        assert_eq!("ab…", truncate("abcd", 3));
+        assert_eq!("æø…", truncate("æøåæøå", 3));
+    }
+
+    #[test]
+    fn it_defaults() {
+        assert_eq!("x", default(&None::<i32>, "x").to_string());
+        assert_eq!("1", default(&Some(1), "x").to_string());
+        assert_eq!("x", default("", "x").to_string());
+        assert_eq!("a", default(&"a".to_owned(), "x").to_string());
+    }
+
+    #[test]
+    fn it_joins() {
+        assert_eq!("1, 2, 3", join(&vec![1, 2, 3], ", "));
+        assert_eq!("", join(&Vec::<i32>::new(), ", "));
+    }
+
+    #[test]
+    fn it_urlencodes() {
+        assert_eq!("a%20b%26c~%C3%A6", urlencode("a b&c~æ"));
+    }
+
+    #[test]
+    fn it_pluralizes() {
+        assert_eq!("", pluralize(&1usize));
+        assert_eq!("s", pluralize(&0i32));
+        assert_eq!("s", pluralize(&2u8));
+    }
+}
@@ -146,6 +146,7 @@ templates.
 
 mod conditional;
 mod display_html_safe;
+pub mod filters;
 mod negative_iterator;
 
 // With specialization, DisplayHtmlSafe could be something that the
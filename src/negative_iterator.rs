@@ -33,7 +33,7 @@ impl<'a, T, E> NegativeIterator for &'a Result<T, E> {
     }
 }
 
-impl<'a, T, I, II> NegativeIterator for &'a T
+impl<T, I, II> NegativeIterator for &T
 where
     II: Iterator,
     T: NegativeIterator<Item = I, IntoIter = II>,
@@ -55,6 +55,7 @@ mod test {
     use super::NegativeIterator;
 
     #[test]
+    #[allow(clippy::never_loop)]
     fn option_some() {
         for _ in Some(5).neg_iter() {
             panic!("Should not iterate");
@@ -72,6 +73,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::never_loop)]
     fn result_ok() {
         let result: Result<i32, i32> = Ok(5);
         for _ in (&result).neg_iter() {
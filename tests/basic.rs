@@ -138,3 +138,33 @@ fn function_call() {
 
     assert_eq!("Hello, World", Test.to_string());
 }
+
+#[test]
+fn it_can_skip_the_field_check_for_deref() {
+    struct Inner {
+        name: &'static str,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "Hello, {{name}}"]
+    #[template_skip_field_check]
+    struct Test {
+        inner: Inner,
+    }
+
+    impl std::ops::Deref for Test {
+        type Target = Inner;
+
+        fn deref(&self) -> &Inner {
+            &self.inner
+        }
+    }
+
+    assert_eq!(
+        "Hello, World",
+        Test {
+            inner: Inner { name: "World" }
+        }
+        .to_string()
+    );
+}
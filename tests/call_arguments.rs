@@ -0,0 +1,87 @@
+use bart_derive::BartDisplay;
+
+struct Price {
+    amount: f64,
+}
+
+impl Price {
+    fn in_currency(&self, currency: &str) -> String {
+        format!("{:.2} {}", self.amount, currency)
+    }
+
+    fn scaled(&self, factor: f64, round: bool) -> f64 {
+        match round {
+            true => (self.amount * factor).round(),
+            false => self.amount * factor,
+        }
+    }
+}
+
+#[test]
+fn it_passes_literal_arguments() {
+    #[derive(BartDisplay)]
+    #[template_string = r#"{{price.in_currency("NOK")}} {{price.scaled(1.5, true)}} {{price.scaled(-2.0, false)}}"#]
+    struct Test {
+        price: Price,
+    }
+
+    assert_eq!(
+        "10.50 NOK 16 -21",
+        Test {
+            price: Price { amount: 10.5 }
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_passes_names_by_reference() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#prices}}{{.in_currency(..currency)}};{{/prices}}"]
+    struct Test {
+        prices: Vec<Price>,
+        currency: &'static str,
+    }
+
+    assert_eq!(
+        "1.00 EUR;2.00 EUR;",
+        Test {
+            prices: vec![Price { amount: 1. }, Price { amount: 2. }],
+            currency: "EUR",
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_calls_functions_with_arguments_in_sections() {
+    struct Words {
+        words: Vec<&'static str>,
+    }
+
+    impl Words {
+        fn starting_with(&self, prefix: &str) -> Vec<&'static str> {
+            self.words
+                .iter()
+                .copied()
+                .filter(|x| x.starts_with(prefix))
+                .collect()
+        }
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = r#"{{#words.starting_with("b")}}{{.}} {{/words.starting_with("b")}}{{#if words.starting_with("x")}}some{{else}}none{{/if}}"#]
+    struct Test {
+        words: Words,
+    }
+
+    assert_eq!(
+        "bar baz none",
+        Test {
+            words: Words {
+                words: vec!["foo", "bar", "baz"]
+            }
+        }
+        .to_string()
+    );
+}
@@ -0,0 +1,38 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_omits_comments() {
+    #[derive(BartDisplay)]
+    #[template_string = "Hello, {{! the name goes here }}{{name}}"]
+    struct Test {
+        name: String,
+    }
+
+    assert_eq!(
+        "Hello, World",
+        Test {
+            name: "World".to_owned()
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_omits_multiline_comments() {
+    #[derive(BartDisplay)]
+    #[template_string = "Hello{{!\n  A comment\n  spanning lines\n}}, World"]
+    struct Test;
+
+    assert_eq!("Hello, World", Test.to_string());
+}
+
+#[test]
+fn it_omits_comments_within_sections() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{! each item }}({{.}}){{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("(1)(2)", Test { items: vec![1, 2] }.to_string());
+}
@@ -0,0 +1,60 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_can_set_delimiters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{=<% %>=}}function() { return <%value%>; }"]
+    struct Test {
+        value: i32,
+    }
+
+    assert_eq!("function() { return 42; }", Test { value: 42 }.to_string());
+}
+
+#[test]
+fn it_can_switch_delimiters_back() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{a}}{{=[[ ]]=}}{{a}}[[a]][[={{ }}=]]{{a}}"]
+    struct Test {
+        a: i32,
+    }
+
+    assert_eq!("1{{a}}11", Test { a: 1 }.to_string());
+}
+
+#[test]
+fn it_supports_sections_with_custom_delimiters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{=<% %>=}}<%#items%>{<%.%>}<%/items%>"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("{1}{2}", Test { items: vec![1, 2] }.to_string());
+}
+
+#[test]
+fn it_supports_unescaped_interpolation_with_custom_delimiters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{=<% %>=}}<%{html}%> <%html%>"]
+    struct Test<'a> {
+        html: &'a str,
+    }
+
+    assert_eq!("<b> &lt;b>", Test { html: "<b>" }.to_string());
+}
+
+#[test]
+fn it_accepts_initial_delimiters_as_attribute() {
+    #[derive(BartDisplay)]
+    #[template_string = "\\begin{<% env %>}{{literal}}\\end{<% env %>}"]
+    #[template_delimiters = "<% %>"]
+    struct Test<'a> {
+        env: &'a str,
+    }
+
+    assert_eq!(
+        "\\begin{center}{{literal}}\\end{center}",
+        Test { env: "center" }.to_string()
+    );
+}
@@ -0,0 +1,116 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_renders_else_clause_for_empty_iteration() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{.}}{{:sep}}, {{else}}No items{{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("1, 2", Test { items: vec![1, 2] }.to_string());
+    assert_eq!("No items", Test { items: vec![] }.to_string());
+}
+
+#[test]
+fn it_iterates_only_once() {
+    use std::cell::Cell;
+
+    struct Once {
+        items: Vec<i32>,
+        iterated: Cell<u32>,
+    }
+
+    impl<'a> IntoIterator for &'a Once {
+        type Item = &'a i32;
+        type IntoIter = std::slice::Iter<'a, i32>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            self.iterated.set(self.iterated.get() + 1);
+            self.items.iter()
+        }
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#once}}{{.}}{{else}}-{{/once}}"]
+    struct Test {
+        once: Once,
+    }
+
+    for items in [vec![], vec![1, 2]] {
+        let test = Test {
+            once: Once {
+                items,
+                iterated: Cell::new(0),
+            },
+        };
+        test.to_string();
+        assert_eq!(1, test.once.iterated.get());
+    }
+}
+
+#[test]
+fn it_iterates_iterators_returned_from_calls() {
+    struct Numbers {
+        items: Vec<i32>,
+    }
+
+    impl Numbers {
+        fn evens(&self) -> impl Iterator<Item = &i32> {
+            self.items.iter().filter(|x| *x % 2 == 0)
+        }
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#numbers.evens()}}{{.}}{{:sep}},{{else}}none{{/numbers.evens()}}"]
+    struct Test {
+        numbers: Numbers,
+    }
+
+    let test = |items| Test {
+        numbers: Numbers { items },
+    };
+    assert_eq!("2,4", test(vec![1, 2, 3, 4]).to_string());
+    assert_eq!("none", test(vec![1, 3]).to_string());
+}
+
+#[test]
+fn it_renders_else_clause_for_conditionals() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#a?}}yes{{else}}no{{/a}} {{^a?}}not{{else}}is{{/a}}"]
+    struct Test {
+        a: bool,
+    }
+
+    assert_eq!("yes is", Test { a: true }.to_string());
+    assert_eq!("no not", Test { a: false }.to_string());
+}
+
+#[test]
+fn it_renders_else_clause_for_scopes() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#people.}}{{.len()}} people{{else}}Nobody{{/people}}"]
+    struct Test {
+        people: Vec<&'static str>,
+    }
+
+    assert_eq!(
+        "2 people",
+        Test {
+            people: vec!["a", "b"]
+        }
+        .to_string()
+    );
+    assert_eq!("Nobody", Test { people: vec![] }.to_string());
+}
+
+#[test]
+fn it_strips_standalone_else_lines() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}\n{{.}}\n{{else}}\nnone\n{{/items}}\n"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("none\n", Test { items: vec![] }.to_string());
+}
@@ -0,0 +1,186 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_applies_filters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{name | upper}} {{name | lower}} {{name | capitalize}}"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!("BOB bob Bob", Test { name: "bOB" }.to_string());
+}
+
+#[test]
+fn it_chains_filters() {
+    #[derive(BartDisplay)]
+    #[template_string = "[{{ title | trim | truncate(8) | upper }}]"]
+    struct Test {
+        title: String,
+    }
+
+    assert_eq!(
+        "[A LONG …]",
+        Test {
+            title: "  A long title ".to_owned()
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_passes_names_as_arguments() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#users}}{{.nick | default(..anonymous)}} {{/users}}"]
+    struct Test {
+        users: Vec<User>,
+        anonymous: &'static str,
+    }
+
+    struct User {
+        nick: Option<&'static str>,
+    }
+
+    assert_eq!(
+        "bob anon ",
+        Test {
+            users: vec![User { nick: Some("bob") }, User { nick: None }],
+            anonymous: "anon",
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_supports_collection_filters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{count}} item{{count | pluralize}}: {{items | join(\", \")}}"]
+    struct Test {
+        count: usize,
+        items: Vec<&'static str>,
+    }
+
+    assert_eq!(
+        "2 items: a, b",
+        Test {
+            count: 2,
+            items: vec!["a", "b"],
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_escapes_filtered_values() {
+    #[derive(BartDisplay)]
+    #[template_string = "<a href=\"?q={{query | urlencode}}\">{{query | upper}}</a> {{{query | upper}}}"]
+    struct Test {
+        query: &'static str,
+    }
+
+    assert_eq!(
+        "<a href=\"?q=%3Cb%3E%20%26\">&lt;B> &amp;</a> <B> &",
+        Test { query: "<b> &" }.to_string()
+    );
+}
+
+#[test]
+fn it_combines_filters_and_format_specs() {
+    #[derive(BartDisplay)]
+    #[template_string = "[{{name | upper:>5}}]"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!("[  BOB]", Test { name: "bob" }.to_string());
+}
+
+fn shout(value: &str) -> String {
+    format!("{}!", value)
+}
+
+fn repeat(value: &str, times: usize) -> String {
+    value.repeat(times)
+}
+
+#[test]
+fn it_calls_user_functions_as_filters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{name | shout}} {{name | repeat(2) | upper}}"]
+    struct Test {
+        name: String,
+    }
+
+    assert_eq!(
+        "hi! HIHI",
+        Test {
+            name: "hi".to_owned()
+        }
+        .to_string()
+    );
+}
+
+mod view_filters {
+    pub fn money(value: &f64) -> String {
+        format!("${:.2}", value)
+    }
+
+    pub fn initials(value: &str, separator: &str) -> String {
+        value
+            .split_whitespace()
+            .filter_map(|word| word.chars().next())
+            .map(|ch| ch.to_string())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+#[test]
+fn it_resolves_filters_in_filters_module() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{name | initials(\".\") | lower}}: {{price | money}}"]
+    #[template_filters = "view_filters"]
+    struct Test {
+        name: &'static str,
+        price: f64,
+    }
+
+    assert_eq!(
+        "a.b: $3.50",
+        Test {
+            name: "Alice Bob",
+            price: 3.5
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_uses_filters_module_in_partials() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/filters/it_uses_filters_module_in_partials.html"]
+    #[template_filters = "self::view_filters"]
+    struct Test {
+        price: f64,
+    }
+
+    assert_eq!("Total: $12.00\n", Test { price: 12. }.to_string());
+}
+
+mod shouting_filters {
+    pub fn upper<T: std::fmt::Display>(value: &T) -> String {
+        format!("{}!", value.to_string().to_uppercase())
+    }
+}
+
+#[test]
+fn it_prefers_filters_module_to_built_in_filters() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{name | upper}} {{name | lower}}"]
+    #[template_filters = "shouting_filters"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!("ALICE! alice", Test { name: "Alice" }.to_string());
+}
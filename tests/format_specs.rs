@@ -0,0 +1,83 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_applies_precision() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{price:.2}}"]
+    struct Test {
+        price: f64,
+    }
+
+    assert_eq!("12.35", Test { price: 12.3456 }.to_string());
+}
+
+#[test]
+fn it_applies_width_and_alignment() {
+    #[derive(BartDisplay)]
+    #[template_string = "[{{id:08}}] [{{name:>6}}] [{{name:*<6}}]"]
+    struct Test {
+        id: u32,
+        name: &'static str,
+    }
+
+    assert_eq!(
+        "[00000042] [   Bob] [Bob***]",
+        Test {
+            id: 42,
+            name: "Bob"
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_supports_other_formatting_traits() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{ratio:e}} {{flags:#x}} {{flags:b}} {{list:?}}"]
+    struct Test {
+        ratio: f64,
+        flags: u8,
+        list: Vec<i32>,
+    }
+
+    assert_eq!(
+        "1.5e3 0xff 11111111 [1, 2]",
+        Test {
+            ratio: 1500.,
+            flags: 255,
+            list: vec![1, 2],
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_escapes_formatted_values() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{name:?}} {{{name:?}}}"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!(
+        "&quot;&lt;b>&quot; \"<b>\"",
+        Test { name: "<b>" }.to_string()
+    );
+}
+
+#[test]
+fn it_applies_format_specs_in_sections() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#values}}{{.:>3}}{{/values}}"]
+    struct Test {
+        values: Vec<i32>,
+    }
+
+    assert_eq!(
+        "  1 22333",
+        Test {
+            values: vec![1, 22, 333]
+        }
+        .to_string()
+    );
+}
@@ -0,0 +1,146 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_chooses_the_first_true_branch() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#if count == 0}}none{{elif count == 1}}one{{else}}{{count}}{{/if}}"]
+    struct Test {
+        count: u32,
+    }
+
+    assert_eq!("none", Test { count: 0 }.to_string());
+    assert_eq!("one", Test { count: 1 }.to_string());
+    assert_eq!("5", Test { count: 5 }.to_string());
+}
+
+#[test]
+fn it_compares_strings() {
+    #[derive(BartDisplay)]
+    #[template_string = r#"{{#if status == "active"}}Active{{/if}}{{#if status != "active"}}Inactive{{/if}}"#]
+    struct Test {
+        status: String,
+    }
+
+    assert_eq!(
+        "Active",
+        Test {
+            status: "active".to_owned()
+        }
+        .to_string()
+    );
+    assert_eq!(
+        "Inactive",
+        Test {
+            status: "suspended".to_owned()
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_combines_conditions() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#if (admin || owner) && !(age < 18)}}yes{{else}}no{{/if}}"]
+    struct Test {
+        admin: bool,
+        owner: bool,
+        age: u8,
+    }
+
+    let test = |admin, owner, age| Test { admin, owner, age }.to_string();
+
+    assert_eq!("yes", test(true, false, 30));
+    assert_eq!("yes", test(false, true, 18));
+    assert_eq!("no", test(false, false, 30));
+    assert_eq!("no", test(true, true, 17));
+}
+
+#[test]
+fn it_applies_not_before_comparing() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#if !done == expected}}yes{{else}}no{{/if}}"]
+    struct Test {
+        done: bool,
+        expected: bool,
+    }
+
+    let test = |done, expected| Test { done, expected }.to_string();
+
+    assert_eq!("yes", test(false, true));
+    assert_eq!("yes", test(true, false));
+    assert_eq!("no", test(true, true));
+}
+
+#[test]
+fn it_tests_names_with_conditional() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#if items && !empty}}{{items.len()}} items{{/if}}"]
+    struct Test {
+        items: Vec<i32>,
+        empty: bool,
+    }
+
+    assert_eq!(
+        "2 items",
+        Test {
+            items: vec![1, 2],
+            empty: false
+        }
+        .to_string()
+    );
+    assert_eq!(
+        "",
+        Test {
+            items: vec![],
+            empty: false
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_uses_the_enclosing_scope() {
+    struct Item {
+        price: f64,
+        name: &'static str,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{#if .price > 10.0 && @index1 < 3}}{{.name}} {{/if}}{{/items}}"]
+    struct Test {
+        items: Vec<Item>,
+    }
+
+    assert_eq!(
+        "b ",
+        Test {
+            items: vec![
+                Item {
+                    price: 5.0,
+                    name: "a"
+                },
+                Item {
+                    price: 20.0,
+                    name: "b"
+                },
+                Item {
+                    price: 30.0,
+                    name: "c"
+                },
+            ]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_strips_standalone_lines() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#if a}}\nA\n{{elif b}}\nB\n{{/if}}\n"]
+    struct Test {
+        a: bool,
+        b: bool,
+    }
+
+    assert_eq!("B\n", Test { a: false, b: true }.to_string());
+}
@@ -0,0 +1,50 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_overrides_blocks() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/inheritance/it_overrides_blocks.html"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!(
+        "<title>Hello, World</title>\nNo content\n",
+        Test { name: "World" }.to_string()
+    );
+}
+
+#[test]
+fn it_inherits_from_multiple_levels() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/inheritance/it_inherits_from_multiple_levels.html"]
+    struct Test {
+        text: &'static str,
+    }
+
+    assert_eq!(
+        "<title>Section</title>\n<section>\n<p>Hello</p>\n</section>\n",
+        Test { text: "Hello" }.to_string()
+    );
+}
+
+#[test]
+fn it_renders_blocks_in_the_current_scope() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/inheritance/it_renders_blocks_in_the_current_scope.html"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("[1][2]\n", Test { items: vec![1, 2] }.to_string());
+}
+
+#[test]
+fn it_inherits_from_the_partials_dir_in_inline_templates() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{<layout.html}}{{$content}}Inline\n{{/content}}{{/layout.html}}"]
+    #[template_partials_dir = "tests/templates/inheritance"]
+    struct Test {}
+
+    assert_eq!("<title>Untitled</title>\nInline\n", Test {}.to_string());
+}
@@ -0,0 +1,79 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_counts_iterations() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{@index}}{{@index1}}{{.}} {{/items}}"]
+    struct Test {
+        items: Vec<&'static str>,
+    }
+
+    assert_eq!(
+        "01a 12b 23c ",
+        Test {
+            items: vec!["a", "b", "c"]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_detects_first_and_last() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{#@first?}}[{{/@first}}{{.}}{{^@last?}}, {{/@last}}{{#@last?}}]{{/@last}}{{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!(
+        "[1, 2, 3]",
+        Test {
+            items: vec![1, 2, 3]
+        }
+        .to_string()
+    );
+    assert_eq!("[1]", Test { items: vec![1] }.to_string());
+}
+
+#[test]
+fn it_gives_the_length() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{@index1}}/{{@length}} {{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("1/2 2/2 ", Test { items: vec![1, 2] }.to_string());
+}
+
+#[test]
+fn it_refers_to_enclosing_loops_with_leading_dots() {
+    struct Row {
+        cells: Vec<i32>,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#rows}}{{#.cells}}{{..@index}}.{{.@index}} {{/.cells}}{{/rows}}"]
+    struct Test {
+        rows: Vec<Row>,
+    }
+
+    assert_eq!(
+        "0.0 0.1 1.0 ",
+        Test {
+            rows: vec![Row { cells: vec![1, 2] }, Row { cells: vec![3] }]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_supports_format_specs() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{@index1:02}} {{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("01 02 ", Test { items: vec![1, 2] }.to_string());
+}
@@ -0,0 +1,91 @@
+use bart_derive::BartDisplay;
+
+#[allow(dead_code)]
+enum Status {
+    Active,
+    Suspended { until: &'static str },
+    Deleted(u32, &'static str),
+}
+
+#[test]
+fn it_matches_variants() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#match status}}{{:Active}}active{{:Suspended}}until {{.until}}{{:Deleted}}by {{.1}}{{/match}}"]
+    struct Test {
+        status: Status,
+    }
+
+    let test = |status| Test { status }.to_string();
+    assert_eq!("active", test(Status::Active));
+    assert_eq!(
+        "until tomorrow",
+        test(Status::Suspended { until: "tomorrow" })
+    );
+    assert_eq!("by admin", test(Status::Deleted(1, "admin")));
+}
+
+#[test]
+fn it_renders_else_clause_for_other_variants() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#match status}}\n{{:Active}}\nactive\n{{else}}\ninactive\n{{/match}}\n"]
+    struct Test<'a> {
+        status: &'a Status,
+    }
+
+    assert_eq!(
+        "active\n",
+        Test {
+            status: &Status::Active
+        }
+        .to_string()
+    );
+    assert_eq!(
+        "inactive\n",
+        Test {
+            status: &Status::Deleted(1, "admin")
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_matches_qualified_variants_of_nested_values() {
+    struct User {
+        status: Status,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#users}}{{#match .status}}{{:Status::Suspended}}{{name}} {{.until}}{{else}}-{{/match}}{{/users}}"]
+    struct Test {
+        users: Vec<User>,
+        name: &'static str,
+    }
+
+    assert_eq!(
+        "-x y",
+        Test {
+            users: vec![
+                User {
+                    status: Status::Active
+                },
+                User {
+                    status: Status::Suspended { until: "y" }
+                },
+            ],
+            name: "x",
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_matches_options() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#match value}}{{:Some}}{{.0}}{{:None}}nothing{{/match}}"]
+    struct Test {
+        value: Option<i32>,
+    }
+
+    assert_eq!("1", Test { value: Some(1) }.to_string());
+    assert_eq!("nothing", Test { value: None }.to_string());
+}
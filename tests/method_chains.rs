@@ -0,0 +1,78 @@
+use bart_derive::BartDisplay;
+
+struct Profile {
+    name: String,
+    tags: Vec<&'static str>,
+}
+
+impl Profile {
+    fn display_name(&self) -> &str {
+        &self.name
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        &self.tags
+    }
+}
+
+struct User {
+    profile: Profile,
+    active: bool,
+}
+
+impl User {
+    fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn greeting(&self, greeting: &str) -> String {
+        format!("{}, {}", greeting, self.profile.name)
+    }
+}
+
+fn user() -> User {
+    User {
+        profile: Profile {
+            name: "Ola".to_owned(),
+            tags: vec!["admin", "staff"],
+        },
+        active: true,
+    }
+}
+
+#[test]
+fn it_calls_functions_in_intermediate_segments() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{user.profile().name}} {{user.profile().display_name()}} {{user.profile().name.len()}}"]
+    struct Test {
+        user: User,
+    }
+
+    assert_eq!("Ola Ola 3", Test { user: user() }.to_string());
+}
+
+#[test]
+fn it_chains_calls_returning_owned_values() {
+    #[derive(BartDisplay)]
+    #[template_string = r#"{{user.greeting("Hi").len()}} {{user.greeting("Hello") | upper}}"#]
+    struct Test {
+        user: User,
+    }
+
+    assert_eq!("7 HELLO, OLA", Test { user: user() }.to_string());
+}
+
+#[test]
+fn it_chains_calls_in_sections_and_expressions() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#user.profile().tags()}}{{.}} {{/user.profile().tags()}}{{#if user.profile().tags().len() > 1 && user.is_active()}}many{{/if}}"]
+    struct Test {
+        user: User,
+    }
+
+    assert_eq!("admin staff many", Test { user: user() }.to_string());
+}
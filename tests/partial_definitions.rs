@@ -0,0 +1,57 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_works() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define greeting}}Hello, {{name}}{{/define}}({{>greeting}})"]
+    struct Test {
+        name: &'static str,
+    }
+
+    assert_eq!("(Hello, World)", Test { name: "World" }.to_string());
+}
+
+#[test]
+fn it_includes_definitions_with_a_root() {
+    struct Item {
+        name: &'static str,
+    }
+
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/partial_definitions/it_defines_partials_on_lines_of_their_own.html"]
+    struct Test {
+        items: Vec<Item>,
+    }
+
+    assert_eq!(
+        "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n",
+        Test {
+            items: vec![Item { name: "a" }, Item { name: "b" }]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_prefers_definitions_to_files() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define item.html}}[{{.}}]{{/define}}{{#items}}{{>item.html}}{{/items}}"]
+    #[template_partials_dir = "tests/templates/partials"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("[1][2]", Test { items: vec![1, 2] }.to_string());
+}
+
+#[test]
+fn it_falls_back_to_files() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define row}}{{>item.html}}{{/define}}{{#items}}{{>row}}{{/items}}"]
+    #[template_partials_dir = "tests/templates/partials"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("(1)(2)", Test { items: vec![1, 2] }.to_string());
+}
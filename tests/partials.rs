@@ -90,3 +90,92 @@ fn it_finds_partials_relative_to_crate_root() {
         .to_string()
     );
 }
+
+#[test]
+fn it_finds_partials_for_inline_templates_relative_to_crate_root() {
+    #[derive(BartDisplay)]
+    #[template_string = "({{>/tests/templates/partials/hello_world.html}})"]
+    struct Test {
+        name: String,
+    }
+
+    assert_eq!(
+        "(Hello, World)",
+        Test {
+            name: "World".to_owned()
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_finds_partials_in_the_partials_dir() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{>item.html}}{{/items}}"]
+    #[template_partials_dir = "tests/templates/partials"]
+    struct Test<'a> {
+        items: &'a [i32],
+    }
+
+    assert_eq!("(1)(2)", Test { items: &[1, 2] }.to_string());
+}
+
+#[test]
+fn it_accepts_named_arguments() {
+    struct Theme {
+        primary: &'static str,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = r#"{{>button.html label="Save changes" kind=theme.primary}}"#]
+    #[template_partials_dir = "tests/templates/partials"]
+    struct Test {
+        theme: Theme,
+    }
+
+    assert_eq!(
+        "<button class=\"primary\">Save changes</button>",
+        Test {
+            theme: Theme { primary: "primary" }
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_renders_sections_for_named_arguments() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define list}}{{#items}}({{.}}){{/items}}{{#show?}}{{count}}{{/show}}{{/define}}\
+        {{>list items=numbers show=true count=3}}"]
+    struct Test {
+        numbers: Vec<i32>,
+    }
+
+    assert_eq!(
+        "(1)(2)3",
+        Test {
+            numbers: vec![1, 2]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_accepts_named_arguments_in_any_order() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define item}}{{label}}={{count}};{{/define}}\
+        {{>item label=\"a\" count=1}}{{>item count=total label=name}}"]
+    struct Test {
+        name: &'static str,
+        total: u32,
+    }
+
+    assert_eq!(
+        "a=1;b=2;",
+        Test {
+            name: "b",
+            total: 2
+        }
+        .to_string()
+    );
+}
@@ -0,0 +1,68 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_resolves_keywords_to_raw_identifiers() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{type}}"]
+    struct Test {
+        r#type: &'static str,
+    }
+
+    assert_eq!("user", Test { r#type: "user" }.to_string());
+}
+
+#[test]
+fn it_supports_raw_identifiers() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{r#type}}"]
+    struct Test {
+        r#type: &'static str,
+    }
+
+    assert_eq!("user", Test { r#type: "user" }.to_string());
+}
+
+#[test]
+fn it_supports_keywords_in_sections_and_nested_names() {
+    struct Inner {
+        r#match: i32,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#loop}}{{.match}}{{/loop}} {{r#struct.match}}"]
+    struct Test {
+        r#loop: Vec<Inner>,
+        r#struct: Inner,
+    }
+
+    assert_eq!(
+        "12 3",
+        Test {
+            r#loop: vec![Inner { r#match: 1 }, Inner { r#match: 2 }],
+            r#struct: Inner { r#match: 3 },
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_supports_keywords_in_template_root() {
+    struct Inner {
+        r#type: i32,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{type}}"]
+    #[template_root = "r#in"]
+    struct Test {
+        r#in: Inner,
+    }
+
+    assert_eq!(
+        "1",
+        Test {
+            r#in: Inner { r#type: 1 }
+        }
+        .to_string()
+    );
+}
@@ -0,0 +1,117 @@
+use bart_derive::BartDisplay;
+
+struct Node {
+    name: &'static str,
+    children: Vec<Node>,
+}
+
+fn node(name: &'static str, children: Vec<Node>) -> Node {
+    Node { name, children }
+}
+
+#[test]
+fn it_renders_trees() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/recursive_partials/it_renders_trees.html"]
+    struct Test {
+        roots: Vec<Node>,
+    }
+
+    assert_eq!(
+        "<ul><li>a<ul><li>b</li><li>c<ul><li>d</li></ul></li></ul></li><li>e</li></ul>\n",
+        Test {
+            roots: vec![
+                node(
+                    "a",
+                    vec![node("b", vec![]), node("c", vec![node("d", vec![])])]
+                ),
+                node("e", vec![]),
+            ]
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_renders_mutually_recursive_partials() {
+    struct Level {
+        name: &'static str,
+        items: Vec<Level>,
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{>even.html root}}"]
+    #[template_partials_dir = "tests/templates/recursive_partials"]
+    struct Test {
+        root: Level,
+    }
+
+    let level = |name, items| Level { name, items };
+    assert_eq!(
+        "[a(b[c])(d)]",
+        Test {
+            root: level(
+                "a",
+                vec![level("b", vec![level("c", vec![])]), level("d", vec![])]
+            )
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_renders_recursive_definitions() {
+    enum Comment {
+        Deleted,
+        Posted {
+            text: &'static str,
+            replies: Vec<Comment>,
+        },
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define thread}}\
+        {{#match .}}{{:Comment::Deleted}}-{{:Comment::Posted}}{{.text}}{{#.replies}}({{>thread}}){{/.replies}}{{/match}}\
+        {{/define}}\
+        {{>thread comment}}"]
+    struct Test {
+        comment: Comment,
+    }
+
+    let posted = |text, replies| Comment::Posted { text, replies };
+    assert_eq!(
+        "a(b(c))(-)",
+        Test {
+            comment: posted(
+                "a",
+                vec![posted("b", vec![posted("c", vec![])]), Comment::Deleted]
+            )
+        }
+        .to_string()
+    );
+}
+
+#[test]
+fn it_renders_recursive_partials_with_named_arguments() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{*define tree}}{{label}}:{{node.name}}\
+        {{#node.children}}[{{>tree node=. label=\"c\"}}]{{/node.children}}{{/define}}\
+        {{#roots}}{{>tree node=. label=\"r\"}}{{/roots}}"]
+    struct Test {
+        roots: Vec<Node>,
+    }
+
+    assert_eq!(
+        "r:a[c:b[c:d]][c:c]r:e",
+        Test {
+            roots: vec![
+                node(
+                    "a",
+                    vec![node("b", vec![node("d", vec![])]), node("c", vec![])]
+                ),
+                node("e", vec![]),
+            ]
+        }
+        .to_string()
+    );
+}
@@ -0,0 +1,67 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_renders_separator_between_items() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{.}}{{:sep}}, {{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!(
+        "1, 2, 3",
+        Test {
+            items: vec![1, 2, 3]
+        }
+        .to_string()
+    );
+    assert_eq!("1", Test { items: vec![1] }.to_string());
+    assert_eq!("", Test { items: vec![] }.to_string());
+}
+
+#[test]
+fn it_works_with_iterators_of_unknown_length() {
+    struct Evens;
+
+    impl IntoIterator for &Evens {
+        type Item = u32;
+        type IntoIter = std::iter::Filter<std::ops::Range<u32>, fn(&u32) -> bool>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            (1..10).filter(|x| x % 2 == 0)
+        }
+    }
+
+    #[derive(BartDisplay)]
+    #[template_string = "{{#evens}}{{.}}{{:sep}} | {{/evens}}"]
+    struct Test {
+        evens: Evens,
+    }
+
+    assert_eq!("2 | 4 | 6 | 8", Test { evens: Evens }.to_string());
+}
+
+#[test]
+fn it_strips_standalone_separator_lines() {
+    #[derive(BartDisplay)]
+    #[template_string = "<ul>\n{{#items}}\n  <li>{{.}}</li>\n  {{:sep}}\n  <li>-</li>\n{{/items}}\n</ul>\n"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!(
+        "<ul>\n  <li>1</li>\n  <li>-</li>\n  <li>2</li>\n</ul>\n",
+        Test { items: vec![1, 2] }.to_string()
+    );
+}
+
+#[test]
+fn it_sees_the_next_item_in_the_separator() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items}}{{.}}{{:sep}} <{{.}}> {{/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("1 <2> 2", Test { items: vec![1, 2] }.to_string());
+}
@@ -0,0 +1,82 @@
+use bart_derive::BartDisplay;
+
+#[test]
+fn it_strips_standalone_section_lines() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/whitespace/it_strips_standalone_section_lines.html"]
+    struct Test {
+        values: Vec<i32>,
+    }
+
+    assert_eq!(
+        "<ul>\n    <li>1</li>\n    <li>2</li>\n</ul>\n",
+        Test { values: vec![1, 2] }.to_string()
+    );
+}
+
+#[test]
+fn it_strips_standalone_partial_and_comment_lines() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/whitespace/it_strips_standalone_partial_lines.html"]
+    struct Test;
+
+    assert_eq!("<p>\nitem\n</p>\n", Test.to_string());
+}
+
+#[test]
+fn it_keeps_lines_with_other_content() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#a?}} yes\n{{/a}} done\n"]
+    struct Test {
+        a: bool,
+    }
+
+    assert_eq!(" yes\n done\n", Test { a: true }.to_string());
+}
+
+#[test]
+fn it_can_keep_standalone_lines() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/whitespace/it_strips_standalone_section_lines.html"]
+    #[template_keep_standalone_lines]
+    struct Test {
+        values: Vec<i32>,
+    }
+
+    assert_eq!(
+        "<ul>\n\n    <li>1</li>\n\n    <li>2</li>\n\n</ul>\n",
+        Test { values: vec![1, 2] }.to_string()
+    );
+}
+
+#[test]
+fn it_trims_whitespace_next_to_marked_tags() {
+    #[derive(BartDisplay)]
+    #[template_string = "<a>\n    {{~name~}}\n</a> {{~{name}}}"]
+    struct Test<'a> {
+        name: &'a str,
+    }
+
+    assert_eq!("<a>World</a>World", Test { name: "World" }.to_string());
+}
+
+#[test]
+fn it_trims_whitespace_inside_sections() {
+    #[derive(BartDisplay)]
+    #[template_string = "{{#items~}}\n    {{.}},\n{{~/items}}"]
+    struct Test {
+        items: Vec<i32>,
+    }
+
+    assert_eq!("1,2,", Test { items: vec![1, 2] }.to_string());
+}
+
+#[test]
+fn it_trims_whitespace_around_comments_and_partials() {
+    #[derive(BartDisplay)]
+    #[template = "tests/templates/whitespace/it_trims_whitespace_around_partials.html"]
+    struct Test;
+
+    // Whitespace within the partial itself is unaffected
+    assert_eq!("[item\n]", Test.to_string());
+}